@@ -0,0 +1,99 @@
+// Measures how long one increment takes to reach every subscriber of a room.
+// Starts the server in process on an ephemeral port with a temporary directory and connects BENCH_CLIENTS local websocket clients (default 2000).
+
+use futures_util::{SinkExt as _, StreamExt as _};
+use server::config::Config;
+use std::{io::{Read as _, Write as _}, net::{SocketAddr, TcpStream}, time::Instant};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+
+const ROUNDS: i32 = 50;
+
+fn frame(seq: u64, kind: &str) -> Message {
+    Message::text(format!(r#"{{"version":1,"seq":{seq},"type":"{kind}"}}"#))
+}
+
+// only authenticated players may increment, so the sender registers first
+fn register(address: SocketAddr) -> String {
+    let body = r#"{"name":"bench","password":"benchmark"}"#;
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(stream, "POST /api/register HTTP/1.1\r\nHost: 127.0.0.1\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    let session: serde_json::Value = serde_json::from_str(body).unwrap();
+    session["token"].as_str().unwrap().to_string()
+}
+
+fn counter(text: &str, kind: &str) -> Option<i64> {
+    let value: serde_json::Value = serde_json::from_str(text).ok()?;
+    if value["type"] != kind { return None; }
+    value["counter"].as_i64()
+}
+
+#[tokio::main]
+async fn main() {
+    let clients: usize = std::env::var("BENCH_CLIENTS").ok().and_then(|value| value.parse().ok()).unwrap_or(2000);
+    // warnings and errors from the server still show up
+    tracing_subscriber::fmt().with_max_level(tracing::Level::WARN).init();
+    let directory = tempfile::tempdir().unwrap();
+    let mut config = Config { port: 0, data: directory.path().to_path_buf(), ..Config::default() };
+    // one sender increments as fast as rounds complete
+    (config.session_rate.per_second, config.session_rate.burst) = (1_000_000.0, 1_000_000.0);
+    (config.address_rate.per_second, config.address_rate.burst) = (1_000_000.0, 1_000_000.0);
+    // the server gets its own actix system so the clients below keep the multi-threaded runtime to themselves
+    let (bound, address) = std::sync::mpsc::channel();
+    let server = std::thread::spawn(move || actix_web::rt::System::new().block_on(async move {
+        let started = server::start(config)?;
+        bound.send((started.addresses[0], started.server.handle())).unwrap();
+        started.server.await
+    }));
+    let (address, handle) = address.recv().expect("server failed to start");
+    let url = format!("ws://{address}/ws/bench");
+
+    let (receipts, mut received) = mpsc::unbounded_channel();
+    for _ in 0..clients {
+        let (mut socket, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        socket.send(frame(1, "subscribe")).await.unwrap();
+        while let Some(Ok(message)) = socket.next().await {
+            if counter(message.to_text().unwrap_or(""), "ack").is_some() { break; }
+        }
+        let receipts = receipts.clone();
+        tokio::spawn(async move {
+            while let Some(Ok(message)) = socket.next().await {
+                if let Some(counter) = counter(message.to_text().unwrap_or(""), "update") {
+                    let _ = receipts.send((counter, Instant::now()));
+                }
+            }
+        });
+    }
+    println!("connected {clients} clients");
+
+    let (mut sender, _) = tokio_tungstenite::connect_async(format!("{url}?token={}", register(address))).await.unwrap();
+
+    let mut latencies = vec![];
+    for round in 0..ROUNDS {
+        let start = Instant::now();
+        sender.send(frame(round as u64, "increment")).await.unwrap();
+        let mut target = None;
+        let mut last = start;
+        let mut arrived = 0;
+        while arrived < clients {
+            let (counter, at) = received.recv().await.unwrap();
+            if *target.get_or_insert(counter) != counter { continue; }
+            last = last.max(at);
+            arrived += 1;
+        }
+        latencies.push(last - start);
+    }
+
+    latencies.sort();
+    let percentile = |fraction: f64| latencies[((latencies.len() - 1) as f64 * fraction) as usize];
+    println!(
+        "broadcast to {clients} clients over {ROUNDS} rounds: min {:?}, median {:?}, p99 {:?}, max {:?}",
+        latencies[0], percentile(0.5), percentile(0.99), latencies[latencies.len() - 1],
+    );
+
+    handle.stop(false).await;
+    server.join().unwrap().unwrap();
+}
@@ -0,0 +1,116 @@
+use crate::{auth, chat, protocol::ServerMessage, session::SessionId, valid_room_name, Counter};
+use actix_web::{dev::Payload, error, web, FromRequest, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::{future::{ready, Ready}, net::IpAddr};
+use tracing::info;
+
+// the caller presented the configured admin token
+pub struct Admin;
+
+impl FromRequest for Admin {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let data = req.app_data::<web::Data<Counter>>().unwrap();
+        ready(match (&data.config.admin_token, auth::bearer(req)) {
+            (None, _) => Err(error::ErrorForbidden("the admin API is disabled, configure an admin token")),
+            (Some(expected), Some(token)) if same(token, expected) => Ok(Admin),
+            (Some(_), _) => Err(error::ErrorUnauthorized("admin token required")),
+        })
+    }
+}
+
+// compares in constant time so the token cannot be guessed byte by byte
+fn same(token: &str, expected: &str) -> bool {
+    token.len() == expected.len() && token.bytes().zip(expected.bytes()).fold(0, |difference, (left, right)| difference | (left ^ right)) == 0
+}
+
+#[derive(Deserialize)]
+struct SetCounter {
+    counter: i32,
+}
+
+#[derive(Deserialize)]
+struct Announce {
+    text: String,
+}
+
+#[derive(Serialize)]
+struct Session {
+    id: SessionId,
+    room: String,
+    player: Option<String>,
+    peer: Option<IpAddr>,
+    connected_at: u64,
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config.service(web::scope("/admin")
+        .route("/rooms/{room}/counter", web::put().to(set_counter))
+        .route("/rooms/{room}/reset", web::post().to(reset))
+        .route("/sessions", web::get().to(sessions))
+        .route("/sessions/{id}", web::delete().to(kick))
+        .route("/announce", web::post().to(announce)));
+}
+
+async fn set_counter(_: Admin, data: web::Data<Counter>, room: web::Path<String>, body: web::Json<SetCounter>) -> actix_web::Result<HttpResponse> {
+    set(&data, &room, body.counter).await
+}
+
+async fn reset(_: Admin, data: web::Data<Counter>, room: web::Path<String>) -> actix_web::Result<HttpResponse> {
+    set(&data, &room, 0).await
+}
+
+async fn set(data: &Counter, name: &str, counter: i32) -> actix_web::Result<HttpResponse> {
+    if !valid_room_name(name) {
+        return Err(error::ErrorBadRequest("invalid room name"));
+    }
+    let room = data.room(name).await?;
+    let counter = room.set(counter).await;
+    room.sessions.broadcast(&ServerMessage::Update { counter, player: None });
+    info!(room = name, counter, "counter set by admin");
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "room": name, "counter": counter })))
+}
+
+async fn sessions(_: Admin, data: web::Data<Counter>) -> HttpResponse {
+    let sessions: Vec<_> = data.sessions.connected().into_iter()
+        .map(|(id, connected)| Session { id, room: connected.room, player: connected.player, peer: connected.peer, connected_at: connected.since })
+        .collect();
+    HttpResponse::Ok().json(sessions)
+}
+
+async fn kick(_: Admin, data: web::Data<Counter>, id: web::Path<SessionId>) -> actix_web::Result<HttpResponse> {
+    if !data.sessions.kick(*id) {
+        return Err(error::ErrorNotFound("no such session"));
+    }
+    info!(session = *id, "session kicked by admin");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// reaches every session listening to a room, the same ones that see counter updates
+async fn announce(_: Admin, data: web::Data<Counter>, body: web::Json<Announce>) -> actix_web::Result<HttpResponse> {
+    let Announce { text } = body.into_inner();
+    if !chat::valid_text(&text) {
+        return Err(error::ErrorBadRequest(format!("announcements must be 1 to {} characters", chat::MAX_LENGTH)));
+    }
+    info!(%text, "announcement");
+    let message = ServerMessage::Announcement { text };
+    for room in data.rooms.lock().unwrap().values() {
+        room.sessions.broadcast(&message);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_must_match_exactly() {
+        assert!(same("0123456789abcdef", "0123456789abcdef"));
+        assert!(!same("0123456789abcdeg", "0123456789abcdef"));
+        assert!(!same("0123456789abcde", "0123456789abcdef"));
+        assert!(!same("", "0123456789abcdef"));
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{admin, auth::{self, Authenticated}, game, history, metrics, protocol::ServerMessage, todo, valid_room_name, Counter, Room, DEFAULT_ROOM};
+use actix_web::{error, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+struct Target {
+    room: Option<String>,
+}
+
+impl Target {
+    fn name(&self) -> actix_web::Result<&str> {
+        let name = self.room.as_deref().unwrap_or(DEFAULT_ROOM);
+        if !valid_room_name(name) {
+            return Err(error::ErrorBadRequest("invalid room name"));
+        }
+        Ok(name)
+    }
+
+    async fn room(&self, data: &Counter) -> actix_web::Result<(&str, Arc<Room>)> {
+        let name = self.name()?;
+        Ok((name, data.room(name).await?))
+    }
+}
+
+const MAX_POINTS: u64 = 10_000;
+
+// seconds since the epoch, `to` is exclusive
+#[derive(Deserialize)]
+struct Range {
+    from: Option<u64>,
+    to: Option<u64>,
+    bucket: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct History<'a> {
+    room: &'a str,
+    from: u64,
+    to: u64,
+    bucket: u64,
+    points: Vec<history::Sample>,
+}
+
+#[derive(Serialize)]
+struct Score<'a> {
+    room: &'a str,
+    counter: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    player: Option<String>,
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config
+        .route("/health", web::get().to(health))
+        .route("/metrics", web::get().to(metrics))
+        .service(web::scope("/api")
+            .route("/score", web::get().to(score))
+            .route("/score/increment", web::post().to(increment))
+            .route("/score/history", web::get().to(score_history))
+            .configure(auth::configure)
+            .route("/replays/{id}", web::get().to(replay))
+            .configure(todo::configure)
+            .configure(admin::configure));
+}
+
+async fn health() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
+
+async fn metrics(data: web::Data<Counter>) -> HttpResponse {
+    let mut counters: Vec<_> = data.rooms.lock().unwrap().iter().map(|(name, room)| (name.clone(), room.get())).collect();
+    counters.sort();
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics::render(&metrics::METRICS, data.sessions.live(), &counters))
+}
+
+async fn score(data: web::Data<Counter>, target: web::Query<Target>) -> actix_web::Result<HttpResponse> {
+    let name = target.name()?;
+    Ok(HttpResponse::Ok().json(Score { room: name, counter: data.counter(name).await?, player: None }))
+}
+
+async fn increment(data: web::Data<Counter>, target: web::Query<Target>, Authenticated(player): Authenticated) -> actix_web::Result<HttpResponse> {
+    let (name, room) = target.room(&data).await?;
+    let counter = room.increment().await;
+    room.sessions.broadcast(&ServerMessage::Update { counter, player: Some(player.clone()) });
+    Ok(HttpResponse::Ok().json(Score { room: name, counter, player: Some(player) }))
+}
+
+// points sit at multiples of the bucket size, a bucket without changes has no point
+async fn score_history(data: web::Data<Counter>, target: web::Query<Target>, range: web::Query<Range>) -> actix_web::Result<HttpResponse> {
+    let name = target.name()?;
+    let to = range.to.unwrap_or_else(|| history::now() + 1);
+    let from = range.from.unwrap_or(to.saturating_sub(24 * 60 * 60));
+    let bucket = range.bucket.unwrap_or(60);
+    if bucket == 0 || from >= to {
+        return Err(error::ErrorBadRequest("bucket must be positive and from before to"));
+    }
+    if (to - from).div_ceil(bucket) > MAX_POINTS {
+        return Err(error::ErrorBadRequest(format!("at most {MAX_POINTS} buckets, use a larger bucket or a shorter range")));
+    }
+    let (store, room) = (Arc::clone(&data.store), name.to_string());
+    let samples = web::block(move || store.load_history(&room, from, to)).await??;
+    Ok(HttpResponse::Ok().json(History { room: name, from, to, bucket, points: history::downsample(&samples, bucket) }))
+}
+
+async fn replay(data: web::Data<Counter>, id: web::Path<String>) -> actix_web::Result<HttpResponse> {
+    if !game::valid_replay_id(&id) {
+        return Err(error::ErrorNotFound("no such replay"));
+    }
+    let store = Arc::clone(&data.store);
+    let replay = web::block(move || store.load_replay(&id)).await??;
+    match replay {
+        Some(replay) => Ok(HttpResponse::Ok().json(replay)),
+        None => Err(error::ErrorNotFound("no such replay")),
+    }
+}
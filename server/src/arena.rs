@@ -0,0 +1,96 @@
+use crate::{protocol::{ArenaSnake, ServerMessage}, Counter, Room};
+use actix_web::rt;
+use snake_rules::{Direction, Step};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+pub const SIZE: [i32; 3] = [20, 20, 20];
+const TICK: Duration = Duration::from_millis(150);
+
+pub struct Arena {
+    rules: snake_rules::Arena,
+    players: HashMap<u32, Player>,
+    task: Option<rt::task::JoinHandle<()>>,
+}
+
+struct Player {
+    name: Option<String>,
+    run: u32,
+}
+
+impl Default for Arena {
+    fn default() -> Self {
+        let mut rules = snake_rules::Arena::new(SIZE);
+        rules.food_lifetime = 200;
+        rules.food_interval = 4;
+        Self {
+            rules,
+            players: HashMap::new(),
+            task: None,
+        }
+    }
+}
+
+pub fn join(data: &Arc<Counter>, room: &Arc<Room>, name: Option<String>) -> u32 {
+    let mut arena = room.arena.lock().unwrap();
+    let id = arena.rules.join();
+    arena.players.insert(id, Player { name, run: 0 });
+    if arena.task.is_none() {
+        arena.task = Some(rt::spawn(tick(Arc::clone(data), Arc::clone(room))));
+    }
+    id
+}
+
+pub fn leave(room: &Room, id: u32) {
+    let mut arena = room.arena.lock().unwrap();
+    arena.rules.leave(id);
+    arena.players.remove(&id);
+    if arena.players.is_empty() {
+        if let Some(task) = arena.task.take() { task.abort(); }
+        arena.rules.food.clear();
+    }
+}
+
+pub fn steer(room: &Room, id: u32, direction: Direction) {
+    if let Some(snake) = room.arena.lock().unwrap().rules.snakes.get_mut(&id) {
+        snake.set_direction(direction);
+    }
+}
+
+async fn tick(data: Arc<Counter>, room: Arc<Room>) {
+    let mut ticker = rt::time::interval(TICK);
+    let mut tick = 0;
+    loop {
+        ticker.tick().await;
+        tick += 1;
+        let (scored, snapshot) = {
+            let mut arena = room.arena.lock().unwrap();
+            let Arena { rules, players, .. } = &mut *arena;
+            let mut scored = vec![];
+            for (id, step) in rules.run(tick) {
+                let Some(player) = players.get_mut(&id) else { continue };
+                match step {
+                    Step::Ate => {
+                        player.run += 1;
+                        scored.push((player.name.clone(), player.run));
+                    }
+                    Step::Crashed => player.run = 0,
+                    Step::Moved => {}
+                }
+            }
+            let snakes = rules.snakes.iter().map(|(id, snake)| ArenaSnake {
+                id: *id,
+                player: players.get(id).and_then(|player| player.name.clone()),
+                run: players.get(id).map_or(0, |player| player.run),
+                parts: snake.parts.iter().copied().collect(),
+            }).collect();
+            let food = rules.food.iter().map(|food| food.position).collect();
+            (scored, ServerMessage::Arena { tick, snakes, food })
+        };
+        for (name, run) in scored {
+            let counter = room.increment().await;
+            room.sessions.broadcast(&ServerMessage::Update { counter, player: name.clone() });
+            if let Some(name) = name { data.score(&name, run).await; }
+        }
+        room.sessions.broadcast(&snapshot);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::{leaderboard, Counter};
+use actix_web::{dev::Payload, error, http::header, web, FromRequest, HttpRequest, HttpResponse};
+use argon2::{password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString}, Argon2};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{future::{ready, Ready}, io::{self, Write as _}, path::Path, sync::{Arc, LazyLock}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+// signs tokens of the form hex(name).expiry.hex(mac), expiry in seconds since the epoch
+pub struct Signer {
+    secret: Vec<u8>,
+    lifetime: Duration,
+}
+
+impl Signer {
+    pub fn new(secret: Vec<u8>, lifetime: Duration) -> Self {
+        Self { secret, lifetime }
+    }
+
+    fn mac(&self, payload: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).unwrap();
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    pub fn sign(&self, name: &str, now: SystemTime) -> String {
+        let expiry = (now + self.lifetime).duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let payload = format!("{}.{expiry}", hex::encode(name));
+        let mac = hex::encode(self.mac(&payload).finalize().into_bytes());
+        format!("{payload}.{mac}")
+    }
+
+    pub fn verify(&self, token: &str, now: SystemTime) -> Option<String> {
+        let (payload, mac) = token.rsplit_once('.')?;
+        self.mac(payload).verify_slice(&hex::decode(mac).ok()?).ok()?;
+        let (name, expiry) = payload.split_once('.')?;
+        let expiry = UNIX_EPOCH + Duration::from_secs(expiry.parse().ok()?);
+        if expiry <= now { return None; }
+        String::from_utf8(hex::decode(name).ok()?).ok()
+    }
+}
+
+// the configured secret, or one generated once and kept next to the data
+pub fn secret(configured: Option<&str>, directory: &Path) -> io::Result<Vec<u8>> {
+    if let Some(secret) = configured { return Ok(secret.as_bytes().to_vec()); }
+    let path = directory.join("auth.key");
+    match std::fs::read_to_string(&path) {
+        Ok(secret) => hex::decode(secret.trim()).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            let secret: [u8; 32] = rand::random();
+            std::fs::create_dir_all(directory)?;
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            // only the server's own user gets to read the key
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            options.open(&path)?.write_all(hex::encode(secret).as_bytes())?;
+            Ok(secret.to_vec())
+        }
+        Err(error) => Err(error),
+    }
+}
+
+// checked when the account does not exist, so unknown names take as long to refuse as wrong passwords
+static DUMMY_HASH: LazyLock<String> = LazyLock::new(|| hash_password("not anyone's password"));
+
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default().hash_password(password.as_bytes(), &salt).unwrap().to_string()
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+}
+
+fn valid_password(password: &str) -> bool {
+    (8..=128).contains(&password.chars().count())
+}
+
+pub fn bearer(req: &HttpRequest) -> Option<&str> {
+    req.headers().get(header::AUTHORIZATION)?.to_str().ok()?.strip_prefix("Bearer ")
+}
+
+// the player behind a valid bearer token, required by every endpoint that changes something
+pub struct Authenticated(pub String);
+
+impl FromRequest for Authenticated {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let data = req.app_data::<web::Data<Counter>>().unwrap();
+        let player = bearer(req).and_then(|token| data.signer.verify(token, SystemTime::now()));
+        ready(player.map(Authenticated).ok_or_else(|| error::ErrorUnauthorized("log in first")))
+    }
+}
+
+#[derive(Deserialize)]
+struct Credentials {
+    name: String,
+    password: String,
+}
+
+#[derive(Serialize)]
+struct Session {
+    name: String,
+    token: String,
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config
+        .route("/register", web::post().to(register))
+        .route("/login", web::post().to(login));
+}
+
+async fn register(data: web::Data<Counter>, credentials: web::Json<Credentials>) -> actix_web::Result<HttpResponse> {
+    let Credentials { name, password } = credentials.into_inner();
+    if !leaderboard::valid_name(&name) {
+        return Err(error::ErrorBadRequest("names must be 1 to 32 printable characters without surrounding whitespace"));
+    }
+    if !valid_password(&password) {
+        return Err(error::ErrorBadRequest("passwords must be 8 to 128 characters"));
+    }
+    let data = data.into_inner();
+    let (registering, account) = (Arc::clone(&data), name.clone());
+    let created = web::block(move || -> io::Result<bool> {
+        let _registering = registering.registering.lock().unwrap();
+        if registering.store.load_account(&account)?.is_some() { return Ok(false); }
+        registering.store.save_account(&account, &hash_password(&password))?;
+        Ok(true)
+    }).await??;
+    if !created { return Err(error::ErrorConflict("name already taken")); }
+    let token = data.signer.sign(&name, SystemTime::now());
+    Ok(HttpResponse::Created().json(Session { name, token }))
+}
+
+async fn login(data: web::Data<Counter>, credentials: web::Json<Credentials>) -> actix_web::Result<HttpResponse> {
+    let Credentials { name, password } = credentials.into_inner();
+    let (store, account) = (Arc::clone(&data.store), name.clone());
+    let valid = web::block(move || -> io::Result<bool> {
+        Ok(match store.load_account(&account)? {
+            Some(hash) => verify_password(&password, &hash),
+            None => {
+                verify_password(&password, &DUMMY_HASH);
+                false
+            }
+        })
+    }).await??;
+    if !valid { return Err(error::ErrorUnauthorized("wrong name or password")); }
+    let token = data.signer.sign(&name, SystemTime::now());
+    Ok(HttpResponse::Ok().json(Session { name, token }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_verify_until_they_expire() {
+        let signer = Signer::new(b"secret".to_vec(), Duration::from_secs(60));
+        let now = SystemTime::now();
+        let token = signer.sign("ada.lovelace", now);
+        assert_eq!(signer.verify(&token, now).as_deref(), Some("ada.lovelace"));
+        assert_eq!(signer.verify(&token, now + Duration::from_secs(61)), None);
+    }
+
+    #[test]
+    fn forged_tokens_are_rejected() {
+        let signer = Signer::new(b"secret".to_vec(), Duration::from_secs(60));
+        let now = SystemTime::now();
+        let token = signer.sign("ada", now);
+        let (payload, mac) = token.rsplit_once('.').unwrap();
+        let renamed = format!("{}{}", hex::encode("bob"), &payload[hex::encode("ada").len()..]);
+        assert_eq!(signer.verify(&format!("{renamed}.{mac}"), now), None);
+        assert_eq!(Signer::new(b"other".to_vec(), Duration::from_secs(60)).verify(&token, now), None);
+        assert_eq!(signer.verify("garbage", now), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn generated_keys_are_private_and_kept() {
+        use std::os::unix::fs::PermissionsExt as _;
+        let directory = tempfile::tempdir().unwrap();
+        let generated = secret(None, directory.path()).unwrap();
+        let mode = std::fs::metadata(directory.path().join("auth.key")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(secret(None, directory.path()).unwrap(), generated);
+    }
+
+    #[test]
+    fn passwords_are_hashed_and_verified() {
+        let hash = hash_password("correct horse");
+        assert!(!hash.contains("correct horse"));
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("battery staple", &hash));
+        assert!(!verify_password("correct horse", "not a hash"));
+    }
+}
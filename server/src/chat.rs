@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+pub const MAX_LENGTH: usize = 500;
+// lines a session gets replayed when it starts listening to a room
+pub const HISTORY: usize = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Line {
+    pub player: String,
+    pub text: String,
+    // seconds since the epoch
+    pub at: u64,
+}
+
+pub fn valid_text(text: &str) -> bool {
+    !text.trim().is_empty() && text.chars().count() <= MAX_LENGTH
+}
+
+#[derive(Default)]
+pub struct History {
+    lines: VecDeque<Line>,
+}
+
+impl History {
+    pub fn push(&mut self, line: Line) {
+        if self.lines.len() == HISTORY { self.lines.pop_front(); }
+        self.lines.push_back(line);
+    }
+
+    pub fn lines(&self) -> Vec<Line> {
+        self.lines.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_keeps_the_latest_lines() {
+        let mut history = History::default();
+        for at in 0..HISTORY as u64 + 3 {
+            history.push(Line { player: "ada".to_string(), text: at.to_string(), at });
+        }
+        let lines = history.lines();
+        assert_eq!(lines.len(), HISTORY);
+        assert_eq!((lines[0].at, lines[HISTORY - 1].at), (3, HISTORY as u64 + 2));
+    }
+
+    #[test]
+    fn text_must_fit_and_say_something() {
+        assert!(valid_text("hello"));
+        assert!(valid_text(&"é".repeat(MAX_LENGTH)));
+        assert!(!valid_text(&"a".repeat(MAX_LENGTH + 1)));
+        assert!(!valid_text(" \n"));
+    }
+}
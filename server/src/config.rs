@@ -0,0 +1,309 @@
+use clap::Parser;
+use serde::Deserialize;
+use crate::limit::Rate;
+use tracing_subscriber::EnvFilter;
+use std::{net::IpAddr, path::PathBuf, time::Duration};
+
+// long enough for any real setting, short enough that adding it to the current time cannot overflow
+const MAX_DURATION: Duration = Duration::from_secs(10 * 365 * 24 * 60 * 60);
+
+// every setting can come from a flag, an environment variable or the TOML file, in that order of precedence
+#[derive(Parser, Deserialize, Default)]
+#[command(about = "Score, leaderboard and snake server")]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// TOML file with any of the settings below
+    #[arg(long, env = "SERVER_CONFIG")]
+    #[serde(skip)]
+    config: Option<PathBuf>,
+    /// IP address to bind to
+    #[arg(long, env = "SERVER_ADDRESS")]
+    address: Option<String>,
+    /// port to bind to, 0 picks a free one
+    #[arg(long, env = "SERVER_PORT")]
+    port: Option<u16>,
+    /// directory holding persisted state
+    #[arg(long, env = "SERVER_DATA")]
+    data: Option<PathBuf>,
+    /// persistence backend, "file" or "sqlite"
+    #[arg(long, env = "SERVER_STORE")]
+    store: Option<String>,
+    /// largest websocket message accepted, in bytes
+    #[arg(long, env = "SERVER_MAX_FRAME_SIZE")]
+    max_frame_size: Option<usize>,
+    /// seconds between websocket pings
+    #[arg(long, env = "SERVER_HEARTBEAT")]
+    heartbeat: Option<u64>,
+    /// seconds without any traffic before a websocket is evicted
+    #[arg(long, env = "SERVER_IDLE_TIMEOUT")]
+    idle_timeout: Option<u64>,
+    /// seconds a disconnected session can still be resumed
+    #[arg(long, env = "SERVER_RESUME_WINDOW")]
+    resume_window: Option<u64>,
+    /// websocket messages per second a session may sustain
+    #[arg(long, env = "SERVER_MESSAGE_RATE")]
+    message_rate: Option<f64>,
+    /// websocket messages a session may send in a burst
+    #[arg(long, env = "SERVER_MESSAGE_BURST")]
+    message_burst: Option<f64>,
+    /// websocket messages per second all sessions from one IP address may sustain
+    #[arg(long, env = "SERVER_ADDRESS_MESSAGE_RATE")]
+    address_message_rate: Option<f64>,
+    /// websocket messages all sessions from one IP address may send in a burst
+    #[arg(long, env = "SERVER_ADDRESS_MESSAGE_BURST")]
+    address_message_burst: Option<f64>,
+    /// rate limited messages a session may send before it is disconnected
+    #[arg(long, env = "SERVER_MAX_STRIKES")]
+    max_strikes: Option<u32>,
+    /// chat lines per second a session may sustain
+    #[arg(long, env = "SERVER_CHAT_RATE")]
+    chat_rate: Option<f64>,
+    /// chat lines a session may send in a burst
+    #[arg(long, env = "SERVER_CHAT_BURST")]
+    chat_burst: Option<f64>,
+    /// key signing player tokens, generated into the data directory when missing
+    #[arg(long, env = "SERVER_AUTH_SECRET")]
+    auth_secret: Option<String>,
+    /// bearer token for the /api/admin endpoints, which are disabled without one
+    #[arg(long, env = "SERVER_ADMIN_TOKEN")]
+    admin_token: Option<String>,
+    /// seconds a player token stays valid
+    #[arg(long, env = "SERVER_TOKEN_LIFETIME")]
+    token_lifetime: Option<u64>,
+    /// seconds counter history is kept
+    #[arg(long, env = "SERVER_HISTORY_RETENTION")]
+    history_retention: Option<u64>,
+    /// seconds after which counter history is merged into coarser buckets
+    #[arg(long, env = "SERVER_HISTORY_DOWNSAMPLE_AFTER")]
+    history_downsample_after: Option<u64>,
+    /// seconds covered by one bucket of merged counter history
+    #[arg(long, env = "SERVER_HISTORY_BUCKET")]
+    history_bucket: Option<u64>,
+    /// directory with the built site to serve, usually site/dist/site/browser
+    #[arg(long, env = "SERVER_SITE")]
+    site: Option<PathBuf>,
+    /// log filter such as "info" or "server=debug,actix_web=warn"
+    #[arg(long, env = "SERVER_LOG")]
+    log: Option<String>,
+    /// log output, "pretty" or "json"
+    #[arg(long, env = "SERVER_LOG_FORMAT")]
+    log_format: Option<String>,
+    /// origins allowed to open websockets, all when empty
+    #[arg(long = "allowed-origin", env = "SERVER_ALLOWED_ORIGINS", value_delimiter = ',')]
+    allowed_origins: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub address: IpAddr,
+    pub port: u16,
+    pub data: PathBuf,
+    pub store: String,
+    pub max_frame_size: usize,
+    pub heartbeat: Duration,
+    pub idle_timeout: Duration,
+    pub resume_window: Duration,
+    pub session_rate: Rate,
+    pub address_rate: Rate,
+    pub max_strikes: u32,
+    pub chat_rate: Rate,
+    pub auth_secret: Option<String>,
+    pub token_lifetime: Duration,
+    pub admin_token: Option<String>,
+    pub history_retention: Duration,
+    pub history_downsample_after: Duration,
+    pub history_bucket: Duration,
+    pub site: Option<PathBuf>,
+    pub log: String,
+    pub log_format: String,
+    pub allowed_origins: Vec<String>,
+}
+
+// what the server runs with when no flag, variable or file says otherwise
+impl Default for Config {
+    fn default() -> Self {
+        Settings::default().resolve().unwrap()
+    }
+}
+
+impl Config {
+    pub fn load() -> Result<Self, String> {
+        let arguments = Settings::parse();
+        let file = match &arguments.config {
+            Some(path) => {
+                let content = std::fs::read_to_string(path).map_err(|error| format!("cannot read config file {}: {error}", path.display()))?;
+                toml::from_str(&content).map_err(|error| format!("invalid config file {}: {error}", path.display()))?
+            }
+            None => Settings::default(),
+        };
+        arguments.merge(file).resolve()
+    }
+
+    pub fn allows_origin(&self, origin: Option<&str>) -> bool {
+        match origin {
+            Some(origin) => self.allowed_origins.is_empty() || self.allowed_origins.iter().any(|allowed| allowed == origin),
+            None => true,
+        }
+    }
+}
+
+impl Settings {
+    fn merge(self, lower: Settings) -> Settings {
+        Settings {
+            config: self.config,
+            address: self.address.or(lower.address),
+            port: self.port.or(lower.port),
+            data: self.data.or(lower.data),
+            store: self.store.or(lower.store),
+            max_frame_size: self.max_frame_size.or(lower.max_frame_size),
+            heartbeat: self.heartbeat.or(lower.heartbeat),
+            idle_timeout: self.idle_timeout.or(lower.idle_timeout),
+            resume_window: self.resume_window.or(lower.resume_window),
+            message_rate: self.message_rate.or(lower.message_rate),
+            message_burst: self.message_burst.or(lower.message_burst),
+            address_message_rate: self.address_message_rate.or(lower.address_message_rate),
+            address_message_burst: self.address_message_burst.or(lower.address_message_burst),
+            max_strikes: self.max_strikes.or(lower.max_strikes),
+            chat_rate: self.chat_rate.or(lower.chat_rate),
+            chat_burst: self.chat_burst.or(lower.chat_burst),
+            auth_secret: self.auth_secret.or(lower.auth_secret),
+            token_lifetime: self.token_lifetime.or(lower.token_lifetime),
+            admin_token: self.admin_token.or(lower.admin_token),
+            history_retention: self.history_retention.or(lower.history_retention),
+            history_downsample_after: self.history_downsample_after.or(lower.history_downsample_after),
+            history_bucket: self.history_bucket.or(lower.history_bucket),
+            site: self.site.or(lower.site),
+            log: self.log.or(lower.log),
+            log_format: self.log_format.or(lower.log_format),
+            allowed_origins: self.allowed_origins.or(lower.allowed_origins),
+        }
+    }
+
+    fn resolve(self) -> Result<Config, String> {
+        let address = self.address.as_deref().unwrap_or("127.0.0.1");
+        let config = Config {
+            address: address.parse().map_err(|_| format!("address {address:?} is not an IP address"))?,
+            port: self.port.unwrap_or(8080),
+            data: self.data.unwrap_or_else(|| PathBuf::from("./data")),
+            store: self.store.unwrap_or_else(|| "file".to_string()),
+            max_frame_size: self.max_frame_size.unwrap_or(2_usize.pow(20)),
+            heartbeat: Duration::from_secs(self.heartbeat.unwrap_or(5)),
+            idle_timeout: Duration::from_secs(self.idle_timeout.unwrap_or(30)),
+            resume_window: Duration::from_secs(self.resume_window.unwrap_or(60)),
+            session_rate: Rate { per_second: self.message_rate.unwrap_or(20.0), burst: self.message_burst.unwrap_or(40.0) },
+            address_rate: Rate { per_second: self.address_message_rate.unwrap_or(100.0), burst: self.address_message_burst.unwrap_or(200.0) },
+            max_strikes: self.max_strikes.unwrap_or(50),
+            chat_rate: Rate { per_second: self.chat_rate.unwrap_or(1.0), burst: self.chat_burst.unwrap_or(5.0) },
+            auth_secret: self.auth_secret,
+            token_lifetime: Duration::from_secs(self.token_lifetime.unwrap_or(7 * 24 * 60 * 60)),
+            admin_token: self.admin_token,
+            history_retention: Duration::from_secs(self.history_retention.unwrap_or(30 * 24 * 60 * 60)),
+            history_downsample_after: Duration::from_secs(self.history_downsample_after.unwrap_or(24 * 60 * 60)),
+            history_bucket: Duration::from_secs(self.history_bucket.unwrap_or(5 * 60)),
+            site: self.site,
+            log: self.log.unwrap_or_else(|| "info".to_string()),
+            log_format: self.log_format.unwrap_or_else(|| "pretty".to_string()),
+            allowed_origins: self.allowed_origins.unwrap_or_default(),
+        };
+        if !["file", "sqlite"].contains(&config.store.as_str()) {
+            return Err(format!("store {:?} is unknown, expected \"file\" or \"sqlite\"", config.store));
+        }
+        if !(1..=64 * 2_usize.pow(20)).contains(&config.max_frame_size) {
+            return Err(format!("max frame size {} must be between 1 byte and 64 MiB", config.max_frame_size));
+        }
+        for (name, duration) in [
+            ("heartbeat", config.heartbeat),
+            ("idle timeout", config.idle_timeout),
+            ("resume window", config.resume_window),
+            ("history retention", config.history_retention),
+            ("history downsampling age", config.history_downsample_after),
+            ("history bucket", config.history_bucket),
+            ("token lifetime", config.token_lifetime),
+        ] {
+            if duration > MAX_DURATION {
+                return Err(format!("{name} ({}s) must be at most ten years", duration.as_secs()));
+            }
+        }
+        if config.heartbeat.is_zero() {
+            return Err("heartbeat must be at least one second".to_string());
+        }
+        if config.idle_timeout <= config.heartbeat {
+            return Err(format!("idle timeout ({}s) must be longer than the heartbeat ({}s)", config.idle_timeout.as_secs(), config.heartbeat.as_secs()));
+        }
+        for (name, rate) in [("message", config.session_rate), ("address message", config.address_rate), ("chat", config.chat_rate)] {
+            if !(rate.per_second > 0.0 && rate.per_second.is_finite() && rate.burst >= 1.0 && rate.burst.is_finite()) {
+                return Err(format!("{name} rate must be positive and its burst at least 1"));
+            }
+        }
+        if config.auth_secret.as_ref().is_some_and(|secret| secret.len() < 16) {
+            return Err("auth secret must be at least 16 bytes".to_string());
+        }
+        if config.admin_token.as_ref().is_some_and(|token| token.len() < 16) {
+            return Err("admin token must be at least 16 bytes".to_string());
+        }
+        if config.token_lifetime.is_zero() {
+            return Err("token lifetime must be at least one second".to_string());
+        }
+        if config.history_bucket.is_zero() {
+            return Err("history bucket must be at least one second".to_string());
+        }
+        if config.history_downsample_after > config.history_retention {
+            return Err(format!("history retention ({}s) must not be shorter than the downsampling age ({}s)", config.history_retention.as_secs(), config.history_downsample_after.as_secs()));
+        }
+        if let Some(site) = config.site.as_ref().filter(|site| !site.join("index.html").is_file()) {
+            return Err(format!("site directory {} has no index.html, build the site first", site.display()));
+        }
+        if let Err(error) = EnvFilter::try_new(&config.log) {
+            return Err(format!("log filter {:?} is invalid: {error}", config.log));
+        }
+        if !["pretty", "json"].contains(&config.log_format.as_str()) {
+            return Err(format!("log format {:?} is unknown, expected \"pretty\" or \"json\"", config.log_format));
+        }
+        for origin in &config.allowed_origins {
+            let host = origin.strip_prefix("http://").or_else(|| origin.strip_prefix("https://"));
+            if host.is_none_or(|host| host.is_empty() || host.contains('/')) {
+                return Err(format!("allowed origin {origin:?} must look like http://host or https://host:port"));
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_match_the_original_server() {
+        let config = Settings::default().resolve().unwrap();
+        assert_eq!(config.address.to_string(), "127.0.0.1");
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.data, PathBuf::from("./data"));
+        assert_eq!(config.max_frame_size, 2_usize.pow(20));
+    }
+
+    #[test]
+    fn flags_take_precedence_over_the_file() {
+        let file: Settings = toml::from_str("port = 9000\nstore = \"sqlite\"").unwrap();
+        let flags = Settings { port: Some(9001), ..Settings::default() };
+        let config = flags.merge(file).resolve().unwrap();
+        assert_eq!(config.port, 9001);
+        assert_eq!(config.store, "sqlite");
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        assert!(toml::from_str::<Settings>("prot = 9000").is_err());
+        assert!(Settings { address: Some("localhost".to_string()), ..Settings::default() }.resolve().is_err());
+        assert!(Settings { store: Some("redis".to_string()), ..Settings::default() }.resolve().is_err());
+        assert!(Settings { idle_timeout: Some(5), heartbeat: Some(5), ..Settings::default() }.resolve().is_err());
+        assert!(Settings { message_rate: Some(0.0), ..Settings::default() }.resolve().is_err());
+        assert!(Settings { address_message_burst: Some(0.5), ..Settings::default() }.resolve().is_err());
+        assert!(Settings { resume_window: Some(u64::MAX), ..Settings::default() }.resolve().is_err());
+        assert!(Settings { token_lifetime: Some(u64::MAX), ..Settings::default() }.resolve().is_err());
+        assert!(Settings { history_bucket: Some(0), ..Settings::default() }.resolve().is_err());
+        assert!(Settings { history_retention: Some(60), ..Settings::default() }.resolve().is_err());
+        assert!(Settings { log_format: Some("xml".to_string()), ..Settings::default() }.resolve().is_err());
+        assert!(Settings { allowed_origins: Some(vec!["example.com".to_string()]), ..Settings::default() }.resolve().is_err());
+    }
+}
@@ -0,0 +1,256 @@
+use crate::{arena, chat, game, history, hub::Outbox, limit::{Bucket, Limiter, Verdict}, metrics::METRICS, protocol::{self, ClientFrame, ClientMessage, ErrorCode, ServerMessage}, session::{Opened, SessionId}, todo::TodoList, valid_room_name, wire::Encoding, Counter, Room};
+use actix_ws::{AggregatedMessage, AggregatedMessageStream, CloseCode, CloseReason, Session};
+use futures_util::StreamExt as _;
+use std::{net::IpAddr, sync::{atomic::Ordering, Arc}, time::Instant};
+use tokio::sync::Notify;
+use tracing::{debug, debug_span, info, Instrument as _};
+
+pub struct Connection {
+    data: Arc<Counter>,
+    room: Arc<Room>,
+    id: SessionId,
+    token: String,
+    outbox: Outbox,
+    player: Option<String>,
+    subscribed: bool,
+    play: Option<game::Play>,
+    arena_id: Option<u32>,
+    watching: Option<Arc<TodoList>>,
+    limiter: Limiter,
+    chat: Bucket,
+    kick: Arc<Notify>,
+}
+
+impl Connection {
+    pub fn open(data: Arc<Counter>, room: Arc<Room>, session: Session, opened: Opened, address: Option<IpAddr>, player: Option<String>, encoding: Encoding) -> Self {
+        let outbox = Outbox::spawn(session, encoding);
+        if let Some(present) = room.enter(opened.id) {
+            room.sessions.broadcast(&ServerMessage::PlayerJoined { session: opened.id, player: player.clone(), room: present, total: data.sessions.live() });
+        }
+        let welcome = ServerMessage::Welcome { session: opened.id, token: opened.token.clone(), resumed: opened.resumed, counter: room.get() };
+        let _ = outbox.send_message(&welcome);
+        if opened.subscribed { room.listen(opened.id, outbox.clone()); }
+        let limiter = Limiter::new(data.config.session_rate, address, data.config.max_strikes, Instant::now());
+        let chat = Bucket::new(data.config.chat_rate, Instant::now());
+        Self {
+            data,
+            room,
+            id: opened.id,
+            token: opened.token,
+            outbox,
+            player,
+            subscribed: opened.subscribed,
+            play: None,
+            arena_id: None,
+            watching: None,
+            limiter,
+            chat,
+            kick: opened.kick,
+        }
+    }
+
+    pub async fn run(mut self, mut session: Session, mut stream: AggregatedMessageStream) {
+        let mut heartbeat = actix_web::rt::time::interval(self.data.config.heartbeat);
+        let mut last_seen = Instant::now();
+        let mut shutdown = self.data.shutdown.subscribe();
+        let kick = Arc::clone(&self.kick);
+        let reason = loop {
+            let message = tokio::select! {
+                _ = shutdown.wait_for(|stopping| *stopping) => {
+                    let _ = session.close(Some(CloseReason {
+                        code: CloseCode::Restart,
+                        description: Some("server restarting".to_string()),
+                    })).await;
+                    break "server restarting";
+                }
+                _ = kick.notified() => {
+                    let _ = session.close(Some(CloseReason {
+                        code: CloseCode::Policy,
+                        description: Some("kicked by an admin".to_string()),
+                    })).await;
+                    break "kicked";
+                }
+                message = stream.next() => match message {
+                    Some(message) => message,
+                    None => break "stream ended",
+                },
+                _ = heartbeat.tick() => {
+                    if last_seen.elapsed() > self.data.config.idle_timeout {
+                        let _ = session.close(Some(CloseReason {
+                            code: CloseCode::Away,
+                            description: Some("idle timeout".to_string()),
+                        })).await;
+                        break "idle timeout";
+                    }
+                    if session.ping(b"").await.is_err() { break "ping failed"; }
+                    continue;
+                }
+            };
+            last_seen = Instant::now();
+            if let Ok(AggregatedMessage::Text(_) | AggregatedMessage::Binary(_)) = message {
+                METRICS.messages_received.fetch_add(1, Ordering::Relaxed);
+                match self.limiter.check(&self.data.addresses, last_seen) {
+                    Verdict::Allowed => {}
+                    Verdict::Limited => {
+                        debug!("rate limited");
+                        let error = ServerMessage::error(None, ErrorCode::RateLimited, "too many messages, slow down");
+                        if self.outbox.send_message(&error).is_err() { break "outbox full"; }
+                        continue;
+                    }
+                    Verdict::Disconnect => {
+                        let _ = session.close(Some(CloseReason {
+                            code: CloseCode::Policy,
+                            description: Some("rate limit exceeded".to_string()),
+                        })).await;
+                        break "rate limit exceeded";
+                    }
+                }
+            }
+            let decoded = match message {
+                Ok(AggregatedMessage::Text(text)) => protocol::decode(&text),
+                Ok(AggregatedMessage::Binary(bytes)) => protocol::decode_binary(&bytes, self.outbox.encoding),
+                Ok(AggregatedMessage::Ping(bytes)) => {
+                    if session.pong(&bytes).await.is_err() { break "pong failed"; }
+                    continue;
+                }
+                // echo the close so the client's handshake completes instead of waiting for the socket to drop
+                Ok(AggregatedMessage::Close(reason)) => {
+                    let _ = session.close(reason).await;
+                    break "closed by client";
+                }
+                Err(error) => {
+                    debug!(%error, "protocol error");
+                    break "protocol error";
+                }
+                Ok(AggregatedMessage::Pong(_)) => continue,
+            };
+            let reply = match decoded {
+                Ok(frame) => {
+                    let span = debug_span!("message", seq = frame.seq, kind = frame.message.kind());
+                    let reply = self.handle(frame).instrument(span.clone()).await;
+                    span.in_scope(|| debug!(elapsed = ?last_seen.elapsed(), "handled"));
+                    reply
+                }
+                Err(error) => {
+                    debug!(?error, "rejected frame");
+                    error
+                }
+            };
+            if self.outbox.send_message(&reply).is_err() {
+                self.outbox.drop_slow();
+                break "outbox full";
+            }
+        };
+        info!(reason, "websocket closed");
+        let data = Arc::clone(&self.data);
+        self.close();
+        data.evict_idle();
+    }
+
+    async fn handle(&mut self, frame: ClientFrame) -> ServerMessage {
+        let seq = frame.seq;
+        let ack = |counter| ServerMessage::Ack { seq, counter };
+        if self.player.is_none() && frame.message.writes() {
+            return ServerMessage::error(Some(seq), ErrorCode::Unauthorized, "log in to play, chat or change the score");
+        }
+        match frame.message {
+            ClientMessage::Increment => {
+                let counter = self.room.increment().await;
+                self.room.sessions.broadcast(&ServerMessage::Update { counter, player: self.player.clone() });
+                ack(counter)
+            }
+            ClientMessage::Query => ack(self.room.get()),
+            ClientMessage::Subscribe => {
+                self.subscribe();
+                ack(self.room.get())
+            }
+            ClientMessage::Play { difficulty } => {
+                self.subscribe();
+                self.leave_arena();
+                let play = game::Play::start(Arc::clone(&self.data), Arc::clone(&self.room), self.player.clone(), difficulty, self.outbox.clone(), seq);
+                let replay = play.replay.clone();
+                self.play = Some(play);
+                ServerMessage::Started { seq, replay }
+            }
+            ClientMessage::Direction { direction } => match (&self.play, self.arena_id) {
+                (_, Some(id)) if game::valid_direction(direction, arena::SIZE) => {
+                    arena::steer(&self.room, id, snake_rules::Position(direction));
+                    ack(self.room.get())
+                }
+                (Some(play), None) if game::valid_direction(direction, game::SIZE) => {
+                    play.set_direction(snake_rules::Position(direction));
+                    ack(self.room.get())
+                }
+                (None, None) => ServerMessage::error(Some(seq), ErrorCode::NotPlaying, "send play or join_arena before steering"),
+                _ => ServerMessage::error(Some(seq), ErrorCode::InvalidDirection, "direction must be a unit vector within the board"),
+            },
+            ClientMessage::JoinArena => {
+                self.subscribe();
+                self.play = None;
+                self.leave_arena();
+                let snake = arena::join(&self.data, &self.room, self.player.clone());
+                self.arena_id = Some(snake);
+                ServerMessage::Joined { seq, id: snake, size: arena::SIZE }
+            }
+            ClientMessage::LeaveArena => {
+                self.leave_arena();
+                ack(self.room.get())
+            }
+            ClientMessage::Presence => ServerMessage::Presence {
+                seq,
+                room: self.room.present.lock().unwrap().len(),
+                total: self.data.sessions.live(),
+            },
+            ClientMessage::Watch { list } if valid_room_name(&list) => match self.data.todo_list(&list).await {
+                Ok(list) => {
+                    self.unwatch();
+                    list.watchers.subscribe(self.id, self.outbox.clone());
+                    let snapshot = list.snapshot(Some(seq));
+                    self.watching = Some(list);
+                    snapshot
+                }
+                Err(error) => {
+                    tracing::error!(%list, %error, "failed to load todo list");
+                    ServerMessage::error(Some(seq), ErrorCode::InvalidList, "the list could not be loaded")
+                }
+            },
+            ClientMessage::Watch { .. } => ServerMessage::error(Some(seq), ErrorCode::InvalidList, "list names must be 1 to 64 letters, digits, dashes or underscores"),
+            ClientMessage::Chat { text } if !chat::valid_text(&text) => {
+                ServerMessage::error(Some(seq), ErrorCode::InvalidChat, format!("chat lines must be 1 to {} characters", chat::MAX_LENGTH))
+            }
+            ClientMessage::Chat { text } => {
+                if !self.chat.take(Instant::now()) {
+                    return ServerMessage::error(Some(seq), ErrorCode::RateLimited, "chatting too fast, slow down");
+                }
+                self.subscribe();
+                self.room.say(chat::Line { player: self.player.clone().unwrap_or_default(), text, at: history::now() });
+                ack(self.room.get())
+            }
+        }
+    }
+
+    fn subscribe(&mut self) {
+        if !self.subscribed { self.room.listen(self.id, self.outbox.clone()); }
+        self.subscribed = true;
+    }
+
+    fn leave_arena(&mut self) {
+        if let Some(id) = self.arena_id.take() { arena::leave(&self.room, id); }
+    }
+
+    fn unwatch(&mut self) {
+        if let Some(list) = self.watching.take() { list.watchers.unsubscribe(self.id); }
+    }
+
+    fn close(mut self) {
+        self.play = None;
+        self.leave_arena();
+        if self.data.sessions.close(&self.token, self.subscribed) {
+            self.unwatch();
+            self.room.sessions.unsubscribe(self.id);
+        }
+        if let Some(present) = self.room.exit(self.id) {
+            self.room.sessions.broadcast(&ServerMessage::PlayerLeft { session: self.id, player: self.player, room: present, total: self.data.sessions.live() });
+        }
+    }
+}
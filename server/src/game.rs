@@ -0,0 +1,111 @@
+use crate::{hub::Outbox, protocol::ServerMessage, Counter, Room};
+use actix_web::{rt, web};
+use snake_rules::{Direction, Position, Replay, Step, DIRECTIONS};
+use std::{sync::{Arc, Mutex}, time::Duration};
+use tracing::Instrument as _;
+
+pub const SIZE: [i32; 3] = [10, 10, 1];
+
+struct Recorded {
+    id: String,
+    game: snake_rules::Game,
+    replay: Replay,
+}
+
+impl Recorded {
+    fn new() -> Self {
+        let replay = Replay::new(SIZE, rand::random(), 25, 5);
+        Self { id: format!("{:016x}", rand::random::<u64>()), game: replay.game(), replay }
+    }
+}
+
+// a crash ends the game and the next one starts right away, so a replay never outgrows one game
+pub struct Play {
+    pub replay: String,
+    recorded: Arc<Mutex<Recorded>>,
+    data: Arc<Counter>,
+    task: rt::task::JoinHandle<()>,
+}
+
+impl Play {
+    // `started` tells the player about the replay of every game after the first
+    pub fn start(data: Arc<Counter>, room: Arc<Room>, player: Option<String>, difficulty: u8, started: Outbox, seq: u64) -> Self {
+        let recorded = Recorded::new();
+        let replay = recorded.id.clone();
+        let recorded = Arc::new(Mutex::new(recorded));
+        let interval = Duration::from_millis(400 - 3 * difficulty.clamp(1, 100) as u64);
+        let task = rt::spawn(tick(Arc::clone(&data), room, player, Arc::clone(&recorded), interval, started, seq).in_current_span());
+        Self { replay, recorded, data, task }
+    }
+
+    pub fn set_direction(&self, direction: Direction) {
+        let recorded = &mut *self.recorded.lock().unwrap();
+        recorded.replay.steer(&mut recorded.game, direction);
+    }
+}
+
+impl Drop for Play {
+    fn drop(&mut self) {
+        self.task.abort();
+        let (id, replay) = {
+            let recorded = self.recorded.lock().unwrap();
+            (recorded.id.clone(), recorded.replay.clone())
+        };
+        save(&self.data, id, replay);
+    }
+}
+
+fn save(data: &Counter, id: String, replay: Replay) {
+    if replay.ticks == 0 { return; }
+    let Ok(writing) = Arc::clone(&data.writes).try_read_owned() else {
+        tracing::warn!(replay = %id, "replay not saved, state was already flushed");
+        return;
+    };
+    let store = Arc::clone(&data.store);
+    rt::spawn(async move {
+        let _writing = writing;
+        let saving = id.clone();
+        if let Err(error) = web::block(move || store.save_replay(&saving, &replay)).await.map_err(std::io::Error::other).and_then(|result| result) {
+            tracing::error!(replay = %id, %error, "failed to persist replay");
+        }
+    }.in_current_span());
+}
+
+pub fn valid_replay_id(id: &str) -> bool {
+    id.len() == 16 && id.chars().all(|character| character.is_ascii_hexdigit() && !character.is_ascii_uppercase())
+}
+
+pub fn valid_direction(direction: [i32; 3], size: [i32; 3]) -> bool {
+    DIRECTIONS.contains(&Position(direction)) && (0..3).all(|axis| direction[axis] == 0 || size[axis] > 1)
+}
+
+async fn tick(data: Arc<Counter>, room: Arc<Room>, player: Option<String>, recorded: Arc<Mutex<Recorded>>, interval: Duration, started: Outbox, seq: u64) {
+    let mut ticker = rt::time::interval(interval);
+    let mut run = 0;
+    loop {
+        ticker.tick().await;
+        let (tick, step, snake, food, finished) = {
+            let recorded = &mut *recorded.lock().unwrap();
+            let step = recorded.replay.run(&mut recorded.game);
+            let (game, tick) = (&recorded.game, recorded.replay.ticks);
+            let (snake, food) = (game.snake.parts.iter().copied().collect(), game.food.iter().map(|food| food.position).collect());
+            let finished = (step == Step::Crashed).then(|| (std::mem::replace(recorded, Recorded::new()), recorded.id.clone()));
+            (tick, step, snake, food, finished)
+        };
+        if let Some((finished, replay)) = finished {
+            save(&data, finished.id, finished.replay);
+            let _ = started.send_message(&ServerMessage::Started { seq, replay });
+        }
+        match step {
+            Step::Ate => {
+                run += 1;
+                let counter = room.increment().await;
+                room.sessions.broadcast(&ServerMessage::Update { counter, player: player.clone() });
+                if let Some(name) = &player { data.score(name, run).await; }
+            }
+            Step::Crashed => run = 0,
+            Step::Moved => {}
+        }
+        room.sessions.broadcast(&ServerMessage::State { player: player.clone(), tick, run, snake, food });
+    }
+}
@@ -0,0 +1,115 @@
+use crate::{store::ScoreStore, Counter};
+use actix_web::{rt, web};
+use serde::{Deserialize, Serialize};
+use std::{io, sync::Arc, time::{Duration, SystemTime, UNIX_EPOCH}};
+use tracing::{error, info};
+
+const COMPACT_EVERY: Duration = Duration::from_secs(60 * 60);
+
+// the counter at the end of a span of seconds and how many changes led there
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    pub at: u64,
+    pub counter: i32,
+    pub changes: u32,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+// adds a change to samples waiting to be written, changes within the same second share one sample
+pub fn record(pending: &mut Vec<Sample>, at: u64, counter: i32) {
+    match pending.last_mut() {
+        Some(last) if last.at == at => {
+            last.counter = counter;
+            last.changes += 1;
+        }
+        _ => pending.push(Sample { at, counter, changes: 1 }),
+    }
+}
+
+// merges time ordered samples into buckets aligned to multiples of `bucket` seconds
+pub fn downsample(samples: &[Sample], bucket: u64) -> Vec<Sample> {
+    let mut merged: Vec<Sample> = Vec::new();
+    for sample in samples {
+        let at = sample.at - sample.at % bucket;
+        match merged.last_mut() {
+            Some(last) if last.at == at => {
+                last.counter = sample.counter;
+                last.changes += sample.changes;
+            }
+            _ => merged.push(Sample { at, ..*sample }),
+        }
+    }
+    merged
+}
+
+// drops samples past the retention and merges the ones older than `downsample_after` into coarser buckets
+pub fn compact(store: &dyn ScoreStore, room: &str, now: u64, retention: Duration, downsample_after: Duration, bucket: Duration) -> io::Result<()> {
+    let bucket = bucket.as_secs();
+    let before = now.saturating_sub(downsample_after.as_secs());
+    let before = before - before % bucket;
+    let kept = store.load_history(room, now.saturating_sub(retention.as_secs()), before)?;
+    store.replace_history(room, before, &downsample(&kept, bucket))
+}
+
+pub async fn compact_periodically(data: Arc<Counter>) {
+    let mut ticker = rt::time::interval(COMPACT_EVERY);
+    loop {
+        ticker.tick().await;
+        // shutdown has flushed already once the write guard is taken
+        let Ok(writing) = Arc::clone(&data.writes).try_read_owned() else { return };
+        let compacting = Arc::clone(&data);
+        let result = web::block(move || -> io::Result<usize> {
+            let _writing = writing;
+            let config = &compacting.config;
+            let rooms = compacting.store.history_rooms()?;
+            for room in &rooms {
+                compact(&*compacting.store, room, now(), config.history_retention, config.history_downsample_after, config.history_bucket)?;
+            }
+            Ok(rooms.len())
+        }).await.map_err(io::Error::other).and_then(|result| result);
+        match result {
+            Ok(rooms) => info!(rooms, "history compacted"),
+            Err(error) => error!(%error, "failed to compact history"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::FileStore;
+
+    fn sample(at: u64, counter: i32, changes: u32) -> Sample {
+        Sample { at, counter, changes }
+    }
+
+    #[test]
+    fn changes_in_one_second_share_a_sample() {
+        let mut pending = vec![];
+        record(&mut pending, 10, 1);
+        record(&mut pending, 10, 2);
+        record(&mut pending, 11, 3);
+        assert_eq!(pending, vec![sample(10, 2, 2), sample(11, 3, 1)]);
+    }
+
+    #[test]
+    fn downsampling_keeps_the_last_counter_per_bucket() {
+        let samples = [sample(58, 1, 1), sample(61, 2, 1), sample(119, 5, 3), sample(240, 6, 1)];
+        assert_eq!(downsample(&samples, 60), vec![sample(0, 1, 1), sample(60, 5, 4), sample(240, 6, 1)]);
+    }
+
+    #[test]
+    fn compaction_expires_and_merges_old_samples() {
+        let directory = tempfile::tempdir().unwrap();
+        let store = FileStore::new(directory.path()).unwrap();
+        store.append_history("score", &[sample(100, 1, 1), sample(1_000, 2, 1), sample(1_010, 3, 2), sample(1_950, 4, 1)]).unwrap();
+        let minute = Duration::from_secs(60);
+        compact(&store, "score", 2_000, Duration::from_secs(1_500), Duration::from_secs(120), minute).unwrap();
+        assert_eq!(store.load_history("score", 0, u64::MAX).unwrap(), vec![sample(960, 3, 3), sample(1_950, 4, 1)]);
+        compact(&store, "score", 2_000, Duration::from_secs(1_500), Duration::from_secs(120), minute).unwrap();
+        assert_eq!(store.load_history("score", 0, u64::MAX).unwrap(), vec![sample(960, 3, 3), sample(1_950, 4, 1)]);
+    }
+}
@@ -0,0 +1,113 @@
+use crate::{metrics::METRICS, protocol::ServerMessage, session::SessionId, wire::{Encoder, Encoding}};
+use actix_web::rt;
+use actix_ws::{CloseCode, CloseReason, Session};
+use bytestring::ByteString;
+use std::{collections::HashMap, sync::{atomic::Ordering, Arc, Mutex}};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+pub const QUEUE: usize = 256;
+
+// JSON is encoded once for everyone, binary sessions encode in their writer since deltas differ per session
+pub enum Frame {
+    Text(ByteString),
+    Message(Arc<ServerMessage>),
+}
+
+struct Encoded<'a> {
+    message: &'a ServerMessage,
+    text: Option<ByteString>,
+    shared: Option<Arc<ServerMessage>>,
+}
+
+impl<'a> Encoded<'a> {
+    fn new(message: &'a ServerMessage) -> Self {
+        Self { message, text: None, shared: None }
+    }
+
+    fn frame(&mut self, encoding: Encoding) -> Frame {
+        match encoding {
+            Encoding::Json => Frame::Text(self.text.get_or_insert_with(|| self.message.encode().into()).clone()),
+            Encoding::MessagePack => Frame::Message(Arc::clone(self.shared.get_or_insert_with(|| Arc::new(self.message.clone())))),
+        }
+    }
+}
+
+// bounded queue in front of a session, written out by its own task so no sender ever awaits a socket
+#[derive(Clone)]
+pub struct Outbox {
+    sender: mpsc::Sender<Frame>,
+    session: Session,
+    pub encoding: Encoding,
+}
+
+pub struct Full;
+
+impl Outbox {
+    pub fn spawn(session: Session, encoding: Encoding) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<Frame>(QUEUE);
+        let mut writer = session.clone();
+        rt::spawn(async move {
+            let mut encoder = Encoder::default();
+            while let Some(frame) = receiver.recv().await {
+                let written = match frame {
+                    Frame::Text(text) => writer.text(text).await,
+                    Frame::Message(message) => writer.binary(encoder.encode(&message)).await,
+                };
+                if written.is_err() { break; }
+            }
+        });
+        Self { sender, session, encoding }
+    }
+
+    pub fn send(&self, frame: Frame) -> Result<(), Full> {
+        match self.sender.try_send(frame) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_) | TrySendError::Closed(_)) => Err(Full),
+        }
+    }
+
+    pub fn send_message(&self, message: &ServerMessage) -> Result<(), Full> {
+        self.send(Encoded::new(message).frame(self.encoding))
+    }
+
+    pub fn drop_slow(&self) {
+        let session = self.session.clone();
+        rt::spawn(async move {
+            let _ = session.close(Some(CloseReason {
+                code: CloseCode::Policy,
+                description: Some("too slow to keep up with broadcasts".to_string()),
+            })).await;
+        });
+    }
+}
+
+#[derive(Default)]
+pub struct Hub {
+    subscribers: Mutex<HashMap<SessionId, Outbox>>,
+}
+
+impl Hub {
+    pub fn subscribe(&self, id: SessionId, outbox: Outbox) {
+        self.subscribers.lock().unwrap().insert(id, outbox);
+    }
+
+    pub fn unsubscribe(&self, id: SessionId) {
+        self.subscribers.lock().unwrap().remove(&id);
+    }
+
+    pub fn broadcast(&self, message: &ServerMessage) {
+        let mut encoded = Encoded::new(message);
+        self.subscribers.lock().unwrap().retain(|id, outbox| match outbox.send(encoded.frame(outbox.encoding)) {
+            Ok(()) => {
+                METRICS.broadcasts_sent.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(Full) => {
+                METRICS.broadcast_failures.fetch_add(1, Ordering::Relaxed);
+                tracing::warn!(session = id, "dropping subscriber that cannot keep up with broadcasts");
+                outbox.drop_slow();
+                false
+            }
+        });
+    }
+}
@@ -0,0 +1,93 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const TOP: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Player {
+    pub name: String,
+    pub total: u32,
+    pub best: u32,
+}
+
+#[derive(Default)]
+pub struct Leaderboard {
+    players: HashMap<String, Player>,
+}
+
+impl Leaderboard {
+    pub fn new(players: Vec<Player>) -> Self {
+        Self { players: players.into_iter().map(|player| (player.name.clone(), player)).collect() }
+    }
+
+    // credits one point to `name` whose current run is now `run` points long, returns whether the top changed
+    pub fn score(&mut self, name: &str, run: u32) -> bool {
+        let before = self.top(TOP);
+        let player = self.players.entry(name.to_string()).or_insert_with(|| Player {
+            name: name.to_string(),
+            total: 0,
+            best: 0,
+        });
+        player.total += 1;
+        player.best = player.best.max(run);
+        before != self.top(TOP)
+    }
+
+    pub fn top(&self, amount: usize) -> Vec<Player> {
+        let mut players: Vec<_> = self.players.values().cloned().collect();
+        players.sort_by(|a, b| b.best.cmp(&a.best).then(b.total.cmp(&a.total)).then(a.name.cmp(&b.name)));
+        players.truncate(amount);
+        players
+    }
+
+    pub fn players(&self) -> Vec<Player> {
+        self.players.values().cloned().collect()
+    }
+}
+
+pub fn valid_name(name: &str) -> bool {
+    !name.trim().is_empty() && name == name.trim() && name.chars().count() <= 32 && !name.chars().any(char::is_control)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player(name: &str, total: u32, best: u32) -> Player {
+        Player { name: name.to_string(), total, best }
+    }
+
+    fn names(players: &[Player]) -> Vec<&str> {
+        players.iter().map(|player| player.name.as_str()).collect()
+    }
+
+    #[test]
+    fn ranks_by_best_then_total_then_name() {
+        let leaderboard = Leaderboard::new(vec![player("cy", 9, 3), player("bo", 4, 5), player("al", 9, 3), player("di", 12, 3)]);
+        assert_eq!(names(&leaderboard.top(TOP)), ["bo", "di", "al", "cy"]);
+        assert_eq!(names(&leaderboard.top(2)), ["bo", "di"]);
+    }
+
+    #[test]
+    fn scoring_counts_every_point_and_keeps_the_best_run() {
+        let mut leaderboard = Leaderboard::default();
+        for run in [1, 2, 3, 1] { leaderboard.score("ada", run); }
+        assert_eq!(leaderboard.players(), [player("ada", 4, 3)]);
+    }
+
+    #[test]
+    fn only_changes_to_the_top_are_reported() {
+        let mut leaderboard = Leaderboard::new((0..TOP as u32).map(|rank| player(&format!("p{rank}"), 10, 10 + rank)).collect());
+        // last place is p0 with a best of 10, a newcomer with a shorter run stays out
+        assert!(!leaderboard.score("new", 1));
+        assert_eq!(leaderboard.top(TOP).len(), TOP);
+        assert!(!names(&leaderboard.top(TOP)).contains(&"new"));
+        // tying p0 on best and total leaves the name to decide, which pushes p0 out
+        for run in 2..10 { assert!(!leaderboard.score("new", run)); }
+        assert!(leaderboard.score("new", 10));
+        assert_eq!(names(&leaderboard.top(TOP)).last(), Some(&"new"));
+        assert!(!names(&leaderboard.top(TOP)).contains(&"p0"));
+        // more points for someone already in the top still count as a change
+        assert!(leaderboard.score("p9", 1));
+    }
+}
@@ -0,0 +1,395 @@
+use actix_web::{body::MessageBody, dev::{Server, ServerHandle, ServiceFactory, ServiceRequest, ServiceResponse}, http::header, middleware::Logger, rt, web, App, Error, HttpRequest, HttpResponse, HttpServer};
+use config::Config;
+use protocol::ServerMessage;
+use leaderboard::Leaderboard;
+use serde::Deserialize;
+use session::SessionId;
+use std::{sync::{Arc, Mutex}, collections::HashMap, io, net::SocketAddr, time::{Duration, Instant, SystemTime}};
+use store::ScoreStore;
+use tokio::sync::{watch, RwLock};
+use tracing::{error, info, warn, Instrument as _};
+
+mod api;
+mod admin;
+mod arena;
+mod auth;
+mod chat;
+pub mod config;
+mod connection;
+mod game;
+mod history;
+mod hub;
+mod leaderboard;
+mod limit;
+mod metrics;
+pub mod protocol;
+mod session;
+mod site;
+mod store;
+mod todo;
+pub mod wire;
+
+const DEFAULT_ROOM: &str = "score";
+const DRAIN: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize)]
+struct Connect {
+    resume: Option<String>,
+    token: Option<String>,
+    // "msgpack" switches the session to binary frames, anything else keeps JSON
+    encoding: Option<String>,
+}
+
+async fn echo(req: HttpRequest, stream: web::Payload, data: web::Data<Counter>, connect: web::Query<Connect>) -> Result<HttpResponse, Error> {
+    let room = data.room(DEFAULT_ROOM).await?;
+    join(req, stream, data, room, connect.into_inner()).await
+}
+
+async fn room(req: HttpRequest, stream: web::Payload, data: web::Data<Counter>, name: web::Path<String>, connect: web::Query<Connect>) -> Result<HttpResponse, Error> {
+    if !valid_room_name(&name) {
+        return Ok(HttpResponse::BadRequest().body("invalid room name"));
+    }
+    let room = data.room(&name).await?;
+    join(req, stream, data, room, connect.into_inner()).await
+}
+
+async fn leaderboard(data: web::Data<Counter>) -> HttpResponse {
+    HttpResponse::Ok().json(data.leaderboard.lock().unwrap().top(leaderboard::TOP))
+}
+
+fn valid_room_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 64 && name.chars().all(|character| character.is_ascii_alphanumeric() || character == '-' || character == '_')
+}
+
+async fn join(req: HttpRequest, stream: web::Payload, data: web::Data<Counter>, room: Arc<Room>, connect: Connect) -> Result<HttpResponse, Error> {
+    let origin = req.headers().get(header::ORIGIN).and_then(|origin| origin.to_str().ok());
+    if !data.config.allows_origin(origin) {
+        return Ok(HttpResponse::Forbidden().body("origin not allowed"));
+    }
+    // browsers cannot set headers on websockets, so the token may also come in the query
+    let player = match connect.token.as_deref().or_else(|| auth::bearer(&req)) {
+        Some(token) => match data.signer.verify(token, SystemTime::now()) {
+            Some(player) => Some(player),
+            None => return Ok(HttpResponse::Unauthorized().body("invalid or expired token")),
+        },
+        None => None,
+    };
+    let (res, session, stream) = actix_ws::handle(&req, stream)?;
+    let data = data.into_inner();
+    let address = req.peer_addr().map(|address| address.ip());
+    let connected = session::Connected { room: room.name.clone(), player: player.clone(), peer: address, since: history::now() };
+    let opened = data.sessions.open(connect.resume.as_deref(), connected);
+
+    let stream = stream
+        .max_frame_size(data.config.max_frame_size)
+        .aggregate_continuations()
+        .max_continuation_size(data.config.max_frame_size);
+
+    let span = tracing::info_span!("connection", session = opened.id, peer = address.map(tracing::field::display), room = %room.name, player = player.as_deref());
+    let resumed = opened.resumed;
+    let encoding = wire::Encoding::negotiate(connect.encoding.as_deref());
+    let connection = span.in_scope(|| {
+        info!(resumed, ?encoding, "websocket opened");
+        connection::Connection::open(data, room, session.clone(), opened, address, player, encoding)
+    });
+    rt::spawn(connection.run(session, stream).instrument(span));
+
+    Ok(res)
+}
+
+pub struct Counter {
+    store: Arc<dyn ScoreStore>,
+    config: Config,
+    signer: auth::Signer,
+    registering: Mutex<()>,
+    sessions: session::Registry,
+    addresses: limit::PerAddress,
+    rooms: Mutex<HashMap<String, Arc<Room>>>,
+    todo_lists: Mutex<HashMap<String, Arc<todo::TodoList>>>,
+    leaderboard: Mutex<Leaderboard>,
+    writing_players: Mutex<()>,
+    shutdown: watch::Sender<bool>,
+    // background writes hold a read guard, shutdown takes the write guard to wait for them
+    writes: Arc<RwLock<()>>,
+}
+
+impl Counter {
+    pub fn new(config: Config) -> io::Result<Self> {
+        let store: Arc<dyn ScoreStore> = Arc::new(metrics::Timed(store::open(&config.store, &config.data)?));
+        Ok(Self {
+            leaderboard: Mutex::new(Leaderboard::new(store.load_players()?)),
+            signer: auth::Signer::new(auth::secret(config.auth_secret.as_deref(), &config.data)?, config.token_lifetime),
+            registering: Mutex::new(()),
+            store,
+            sessions: session::Registry::new(config.resume_window),
+            addresses: limit::PerAddress::new(config.address_rate),
+            rooms: Mutex::new(HashMap::new()),
+            todo_lists: Mutex::new(HashMap::new()),
+            writing_players: Mutex::new(()),
+            shutdown: watch::channel(false).0,
+            writes: Arc::new(RwLock::new(())),
+            config,
+        })
+    }
+
+    async fn score(self: &Arc<Self>, name: &str, run: u32) {
+        let changed = self.leaderboard.lock().unwrap().score(name, run);
+        let data = Arc::clone(self);
+        if let Err(error) = web::block(move || data.persist_players()).await.map_err(std::io::Error::other).and_then(|result| result) {
+            error!(%error, "failed to persist players");
+        }
+        if changed {
+            let message = ServerMessage::Leaderboard { players: self.leaderboard.lock().unwrap().top(leaderboard::TOP) };
+            for room in self.rooms.lock().unwrap().values() {
+                room.sessions.broadcast(&message);
+            }
+        }
+    }
+
+    fn persist_players(&self) -> std::io::Result<()> {
+        let _writing = self.writing_players.lock().unwrap();
+        let players = self.leaderboard.lock().unwrap().players();
+        self.store.save_players(&players)
+    }
+
+    // loads on a blocking thread outside the lock, if another task got there first its room is kept
+    async fn room(&self, name: &str) -> std::io::Result<Arc<Room>> {
+        if let Some(room) = self.rooms.lock().unwrap().get(name) { return Ok(Arc::clone(room)); }
+        let (store, loading) = (Arc::clone(&self.store), name.to_string());
+        let counter = web::block(move || store.load(&loading)).await.map_err(std::io::Error::other)??;
+        let mut locked_rooms = self.rooms.lock().unwrap();
+        if let Some(room) = locked_rooms.get(name) { return Ok(Arc::clone(room)); }
+        evict_idle(&mut locked_rooms);
+        let room = Arc::new(Room {
+            name: name.to_string(),
+            store: Arc::clone(&self.store),
+            counter: Mutex::new(counter),
+            writing: Mutex::new(()),
+            history: Mutex::new(Vec::new()),
+            sessions: hub::Hub::default(),
+            present: Mutex::new(HashMap::new()),
+            arena: Mutex::new(arena::Arena::default()),
+            chat: Mutex::new(chat::History::default()),
+        });
+        locked_rooms.insert(name.to_string(), Arc::clone(&room));
+        Ok(room)
+    }
+
+    // the counter of a room without loading the room, so looking never keeps anything around
+    async fn counter(&self, name: &str) -> std::io::Result<i32> {
+        if let Some(room) = self.rooms.lock().unwrap().get(name) { return Ok(room.get()); }
+        let (store, name) = (Arc::clone(&self.store), name.to_string());
+        web::block(move || store.load(&name)).await.map_err(std::io::Error::other)?
+    }
+
+    fn evict_idle(&self) {
+        evict_idle(&mut self.rooms.lock().unwrap());
+    }
+
+    // tries everything even when something fails, so one broken room loses nothing else
+    fn flush(&self) -> std::io::Result<()> {
+        let mut failed = 0;
+        let mut check = |what: &str, result: std::io::Result<()>| if let Err(error) = result {
+            error!(what, %error, "failed to flush");
+            failed += 1;
+        };
+        let rooms: Vec<_> = self.rooms.lock().unwrap().values().cloned().collect();
+        for room in rooms { check(&format!("room {}", room.name), room.persist()); }
+        check("leaderboard", self.persist_players());
+        let lists: Vec<_> = self.todo_lists.lock().unwrap().values().cloned().collect();
+        for list in lists { check(&format!("todo list {}", list.name), list.persist()); }
+        if failed > 0 { return Err(std::io::Error::other(format!("{failed} flushes failed"))); }
+        Ok(())
+    }
+
+    // loaded the same way as rooms
+    async fn todo_list(&self, name: &str) -> std::io::Result<Arc<todo::TodoList>> {
+        if let Some(list) = self.todo_lists.lock().unwrap().get(name) { return Ok(Arc::clone(list)); }
+        let (store, loading) = (Arc::clone(&self.store), name.to_string());
+        let list = web::block(move || todo::TodoList::load(&loading, store)).await.map_err(std::io::Error::other)??;
+        Ok(Arc::clone(self.todo_lists.lock().unwrap().entry(name.to_string()).or_insert_with(|| Arc::new(list))))
+    }
+}
+
+// a room only the map still holds has no connection, arena or write in flight left
+fn evict_idle(rooms: &mut HashMap<String, Arc<Room>>) {
+    rooms.retain(|_, room| Arc::strong_count(room) > 1 || !room.history.lock().unwrap().is_empty() || !room.chat.lock().unwrap().lines().is_empty());
+}
+
+struct Room {
+    name: String,
+    store: Arc<dyn ScoreStore>,
+    counter: Mutex<i32>,
+    writing: Mutex<()>,
+    // changes not yet appended to the stored history
+    history: Mutex<Vec<history::Sample>>,
+    sessions: hub::Hub,
+    // open connections per session, a resumed session briefly has two
+    present: Mutex<HashMap<SessionId, u32>>,
+    arena: Mutex<arena::Arena>,
+    chat: Mutex<chat::History>,
+}
+
+impl Room {
+    fn get(&self) -> i32 {
+        *self.counter.lock().unwrap()
+    }
+
+    // subscribes and replays the chat under its lock, so a line is neither missed nor sent twice
+    fn listen(&self, id: SessionId, outbox: hub::Outbox) {
+        let chat = self.chat.lock().unwrap();
+        let lines = chat.lines();
+        if !lines.is_empty() { let _ = outbox.send_message(&ServerMessage::ChatHistory { lines }); }
+        self.sessions.subscribe(id, outbox);
+    }
+
+    fn say(&self, line: chat::Line) {
+        let mut chat = self.chat.lock().unwrap();
+        chat.push(line.clone());
+        self.sessions.broadcast(&ServerMessage::Chat(line));
+    }
+
+    // the participant count when the session was not here yet
+    fn enter(&self, id: SessionId) -> Option<usize> {
+        let mut present = self.present.lock().unwrap();
+        let connections = present.entry(id).or_default();
+        *connections += 1;
+        (*connections == 1).then_some(present.len())
+    }
+
+    // the participant count when the session's last connection left
+    fn exit(&self, id: SessionId) -> Option<usize> {
+        let mut present = self.present.lock().unwrap();
+        let connections = present.get_mut(&id)?;
+        *connections -= 1;
+        if *connections > 0 { return None; }
+        present.remove(&id);
+        Some(present.len())
+    }
+
+    async fn increment(self: &Arc<Self>) -> i32 {
+        self.change(|counter| counter + 1).await
+    }
+
+    async fn set(self: &Arc<Self>, counter: i32) -> i32 {
+        self.change(|_| counter).await
+    }
+
+    async fn change(self: &Arc<Self>, change: impl FnOnce(i32) -> i32) -> i32 {
+        let counter = {
+            let mut locked_counter = self.counter.lock().unwrap();
+            *locked_counter = change(*locked_counter);
+            history::record(&mut self.history.lock().unwrap(), history::now(), *locked_counter);
+            *locked_counter
+        };
+        let room = Arc::clone(self);
+        if let Err(error) = web::block(move || room.persist()).await.map_err(std::io::Error::other).and_then(|result| result) {
+            error!(room = %self.name, %error, "failed to persist room");
+        }
+        counter
+    }
+
+    // reads the counter under the write lock so a slow write never overwrites a newer value
+    fn persist(&self) -> std::io::Result<()> {
+        let _writing = self.writing.lock().unwrap();
+        let counter = self.get();
+        self.store.save(&self.name, counter)?;
+        let samples = std::mem::take(&mut *self.history.lock().unwrap());
+        if samples.is_empty() { return Ok(()); }
+        self.store.append_history(&self.name, &samples).inspect_err(|_| {
+            // keep them for the next write, in front of anything recorded meanwhile
+            self.history.lock().unwrap().splice(0..0, samples);
+        })
+    }
+}
+
+// like the default format but without the query string, which carries player and resume tokens
+fn access_log() -> Logger {
+    Logger::new(r#"%a "%{method}xi %U" %s %b "%{Referer}i" "%{User-Agent}i" %T"#)
+        .custom_request_replace("method", |req| req.method().to_string())
+}
+
+// every route the server has, around one shared state
+pub fn app(data: web::Data<Counter>) -> App<impl ServiceFactory<ServiceRequest, Config = (), Response = ServiceResponse<impl MessageBody>, Error = Error, InitError = ()>> {
+    let site = data.config.site.clone();
+    App::new()
+        .wrap(access_log())
+        .app_data(data)
+        .route("/echo", web::get().to(echo))
+        .route("/ws/{room}", web::get().to(room))
+        .route("/leaderboard", web::get().to(leaderboard))
+        .configure(api::configure)
+        .configure(|service| if let Some(directory) = &site { site::configure(service, directory) })
+}
+
+pub struct Started {
+    pub server: Server,
+    pub addresses: Vec<SocketAddr>,
+    pub data: Arc<Counter>,
+}
+
+// binds and starts serving, the returned server still has to be awaited inside the actix runtime
+pub fn start(config: Config) -> io::Result<Started> {
+    let data = web::Data::new(Counter::new(config)?);
+    let factory = data.clone();
+    let server = HttpServer::new(move || app(factory.clone()))
+        .disable_signals()
+        .shutdown_timeout(DRAIN.as_secs())
+        .bind((data.config.address, data.config.port))?;
+    let addresses = server.addrs();
+    let server = server.run();
+    let data = data.into_inner();
+    rt::spawn(history::compact_periodically(Arc::clone(&data)));
+    Ok(Started { server, addresses, data })
+}
+
+// stops accepting, closes every websocket, waits for pending writes and flushes everything before the server exits
+pub async fn shutdown(server: ServerHandle, data: Arc<Counter>) {
+    info!("shutting down");
+    server.pause().await;
+    data.shutdown.send_replace(true);
+    let deadline = Instant::now() + DRAIN;
+    while data.sessions.live() > 0 && Instant::now() < deadline {
+        rt::time::sleep(Duration::from_millis(50)).await;
+    }
+    if data.sessions.live() > 0 { warn!(sessions = data.sessions.live(), "sessions still open after draining"); }
+    let writes = Arc::clone(&data.writes);
+    let _writes = writes.write().await;
+    match web::block(move || data.flush()).await.map_err(std::io::Error::other).and_then(|result| result) {
+        Ok(()) => info!("state flushed"),
+        Err(error) => error!(%error, "failed to flush state"),
+    }
+    server.stop(true).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn flush_keeps_going_after_a_failure() {
+        let directory = tempfile::tempdir().unwrap();
+        let data = Counter::new(Config { data: directory.path().to_path_buf(), ..Config::default() }).unwrap();
+        let rooms = [data.room("broken").await.unwrap(), data.room("fine").await.unwrap()];
+        for room in &rooms { *room.counter.lock().unwrap() = 3; }
+        std::fs::create_dir(directory.path().join("broken.txt")).unwrap();
+        assert!(data.flush().is_err());
+        assert_eq!(std::fs::read_to_string(directory.path().join("fine.txt")).unwrap(), "3");
+    }
+
+    #[actix_web::test]
+    async fn idle_rooms_are_dropped() {
+        let directory = tempfile::tempdir().unwrap();
+        let data = Counter::new(Config { data: directory.path().to_path_buf(), ..Config::default() }).unwrap();
+        assert_eq!(data.counter("looked-at").await.unwrap(), 0);
+        let kept = data.room("kept").await.unwrap();
+        assert_eq!(kept.increment().await, 1);
+        drop(data.room("visited").await.unwrap());
+        data.evict_idle();
+        assert_eq!(data.rooms.lock().unwrap().keys().collect::<Vec<_>>(), ["kept"]);
+        drop(kept);
+        data.evict_idle();
+        assert!(data.rooms.lock().unwrap().is_empty());
+        assert_eq!(data.counter("kept").await.unwrap(), 1);
+    }
+}
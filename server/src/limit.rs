@@ -0,0 +1,179 @@
+use std::{collections::HashMap, net::IpAddr, sync::Mutex, time::Instant};
+
+// buckets idle long enough to be full again are forgotten once this many addresses are tracked
+const TRACKED: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    pub per_second: f64,
+    pub burst: f64,
+}
+
+// callers pass the current time in so tests can drive the clock
+pub struct Bucket {
+    rate: Rate,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    pub fn new(rate: Rate, now: Instant) -> Self {
+        Self { rate, tokens: rate.burst, updated: now }
+    }
+
+    pub fn take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens < 1.0 { return false; }
+        self.tokens -= 1.0;
+        true
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate.per_second).min(self.rate.burst);
+        self.updated = now;
+    }
+}
+
+pub struct PerAddress {
+    rate: Rate,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl PerAddress {
+    pub fn new(rate: Rate) -> Self {
+        Self { rate, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn take(&self, address: IpAddr, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= TRACKED {
+            buckets.retain(|_, bucket| {
+                bucket.refill(now);
+                bucket.tokens < bucket.rate.burst
+            });
+        }
+        buckets.entry(address).or_insert_with(|| Bucket::new(self.rate, now)).take(now)
+    }
+}
+
+// a session has to get past both its own bucket and its address's; too many refusals without a quiet
+// spell long enough to fill the bucket in between and it is cut off
+pub struct Limiter {
+    bucket: Bucket,
+    address: Option<IpAddr>,
+    strikes: u32,
+    struck: Instant,
+    max_strikes: u32,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Allowed,
+    Limited,
+    Disconnect,
+}
+
+impl Limiter {
+    pub fn new(rate: Rate, address: Option<IpAddr>, max_strikes: u32, now: Instant) -> Self {
+        Self { bucket: Bucket::new(rate, now), address, strikes: 0, struck: now, max_strikes }
+    }
+
+    pub fn check(&mut self, addresses: &PerAddress, now: Instant) -> Verdict {
+        // no refusal for as long as the bucket takes to fill up means the session slowed down, so earlier ones are forgiven
+        let forgiven = self.bucket.rate.burst / self.bucket.rate.per_second;
+        if now.saturating_duration_since(self.struck).as_secs_f64() >= forgiven { self.strikes = 0; }
+        if self.bucket.take(now) {
+            if self.address.is_none_or(|address| addresses.take(address, now)) { return Verdict::Allowed; }
+            // the address refused it, so the session keeps its token
+            self.bucket.tokens += 1.0;
+        }
+        self.strikes += 1;
+        self.struck = now;
+        if self.strikes > self.max_strikes { Verdict::Disconnect } else { Verdict::Limited }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const RATE: Rate = Rate { per_second: 2.0, burst: 3.0 };
+
+    #[test]
+    fn bucket_allows_a_burst_then_refills_over_time() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new(RATE, start);
+        assert!((0..3).all(|_| bucket.take(start)));
+        assert!(!bucket.take(start));
+        assert!(!bucket.take(start + Duration::from_millis(400)));
+        assert!(bucket.take(start + Duration::from_millis(500)));
+        assert!(!bucket.take(start + Duration::from_millis(500)));
+        let later = start + Duration::from_secs(60);
+        assert!((0..3).all(|_| bucket.take(later)));
+        assert!(!bucket.take(later));
+    }
+
+    #[test]
+    fn sessions_on_one_address_share_its_bucket() {
+        let start = Instant::now();
+        let addresses = PerAddress::new(Rate { per_second: 1.0, burst: 4.0 });
+        let address: IpAddr = "10.0.0.1".parse().unwrap();
+        let mut first = Limiter::new(RATE, Some(address), 10, start);
+        let mut second = Limiter::new(RATE, Some(address), 10, start);
+        let mut elsewhere = Limiter::new(RATE, Some("10.0.0.2".parse().unwrap()), 10, start);
+        assert!((0..3).all(|_| first.check(&addresses, start) == Verdict::Allowed));
+        assert_eq!(second.check(&addresses, start), Verdict::Allowed);
+        assert_eq!(second.check(&addresses, start), Verdict::Limited);
+        assert_eq!(elsewhere.check(&addresses, start), Verdict::Allowed);
+        assert_eq!(second.check(&addresses, start + Duration::from_secs(1)), Verdict::Allowed);
+    }
+
+    #[test]
+    fn address_refusals_leave_the_session_budget_alone() {
+        let start = Instant::now();
+        let addresses = PerAddress::new(Rate { per_second: 1.0, burst: 1.0 });
+        let address: IpAddr = "10.0.0.1".parse().unwrap();
+        let mut limiter = Limiter::new(RATE, Some(address), 10, start);
+        assert_eq!(limiter.check(&addresses, start), Verdict::Allowed);
+        assert!((0..5).all(|_| limiter.check(&addresses, start) == Verdict::Limited));
+        assert_eq!(limiter.bucket.tokens, 2.0);
+    }
+
+    #[test]
+    fn repeat_offenders_are_disconnected() {
+        let start = Instant::now();
+        let addresses = PerAddress::new(RATE);
+        let mut limiter = Limiter::new(RATE, None, 2, start);
+        assert!((0..3).all(|_| limiter.check(&addresses, start) == Verdict::Allowed));
+        assert_eq!(limiter.check(&addresses, start), Verdict::Limited);
+        assert_eq!(limiter.check(&addresses, start), Verdict::Limited);
+        assert_eq!(limiter.check(&addresses, start + Duration::from_secs(1)), Verdict::Allowed);
+        assert_eq!(limiter.check(&addresses, start + Duration::from_secs(1)), Verdict::Allowed);
+        assert_eq!(limiter.check(&addresses, start + Duration::from_secs(1)), Verdict::Disconnect);
+    }
+
+    #[test]
+    fn strikes_are_forgiven_after_a_quiet_spell() {
+        let start = Instant::now();
+        let addresses = PerAddress::new(RATE);
+        let mut limiter = Limiter::new(RATE, None, 2, start);
+        for burst in 0..10 {
+            let now = start + Duration::from_secs(burst * 60);
+            assert!((0..3).all(|_| limiter.check(&addresses, now) == Verdict::Allowed));
+            assert!((0..2).all(|_| limiter.check(&addresses, now) == Verdict::Limited));
+        }
+    }
+
+    #[test]
+    fn idle_addresses_are_forgotten() {
+        let start = Instant::now();
+        let addresses = PerAddress::new(RATE);
+        for index in 0..TRACKED as u32 {
+            addresses.take(IpAddr::from(index.to_be_bytes()), start);
+        }
+        addresses.take("10.0.0.1".parse().unwrap(), start + Duration::from_secs(60));
+        assert_eq!(addresses.buckets.lock().unwrap().len(), 1);
+    }
+}
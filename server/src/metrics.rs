@@ -0,0 +1,166 @@
+use crate::{history::Sample, leaderboard::Player, store::ScoreStore, todo::Todos};
+use snake_rules::Replay;
+use std::{fmt::Write as _, io, sync::{atomic::{AtomicU64, Ordering}, Arc}, time::{Duration, Instant}};
+
+// upper bounds in seconds, the last bucket is +Inf
+const BUCKETS: [f64; 12] = [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+pub static METRICS: Metrics = Metrics {
+    messages_received: AtomicU64::new(0),
+    broadcasts_sent: AtomicU64::new(0),
+    broadcast_failures: AtomicU64::new(0),
+    persist: Histogram::new(),
+};
+
+pub struct Metrics {
+    pub messages_received: AtomicU64,
+    pub broadcasts_sent: AtomicU64,
+    pub broadcast_failures: AtomicU64,
+    pub persist: Histogram,
+}
+
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub const fn new() -> Self {
+        Self { buckets: [const { AtomicU64::new(0) }; BUCKETS.len() + 1], sum_micros: AtomicU64::new(0) }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = BUCKETS.iter().position(|bound| seconds <= *bound).unwrap_or(BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, output: &mut String, name: &str, help: &str) {
+        let _ = writeln!(output, "# HELP {name} {help}\n# TYPE {name} histogram");
+        let mut count = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            count += bucket.load(Ordering::Relaxed);
+            let bound = BUCKETS.get(index).map_or("+Inf".to_string(), |bound| bound.to_string());
+            let _ = writeln!(output, "{name}_bucket{{le=\"{bound}\"}} {count}");
+        }
+        let _ = writeln!(output, "{name}_sum {}", self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6);
+        let _ = writeln!(output, "{name}_count {count}");
+    }
+}
+
+fn counter(output: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    let _ = writeln!(output, "# HELP {name} {help}\n# TYPE {name} counter\n{name} {}", value.load(Ordering::Relaxed));
+}
+
+// Prometheus text exposition of everything above plus the gauges only the caller knows
+pub fn render(metrics: &Metrics, sessions: usize, counters: &[(String, i32)]) -> String {
+    let mut output = String::new();
+    let _ = writeln!(output, "# HELP server_sessions Connected websocket sessions.\n# TYPE server_sessions gauge\nserver_sessions {sessions}");
+    counter(&mut output, "server_messages_received_total", "Websocket messages received from clients.", &metrics.messages_received);
+    counter(&mut output, "server_broadcasts_sent_total", "Messages queued to subscribers by broadcasts.", &metrics.broadcasts_sent);
+    counter(&mut output, "server_broadcast_failures_total", "Subscribers dropped because their queue was full or closed.", &metrics.broadcast_failures);
+    metrics.persist.render(&mut output, "server_persist_seconds", "Time spent writing to the store.");
+    let _ = writeln!(output, "# HELP server_counter Current counter of each loaded room.\n# TYPE server_counter gauge");
+    for (room, value) in counters {
+        let _ = writeln!(output, "server_counter{{room=\"{room}\"}} {value}");
+    }
+    output
+}
+
+// times every write that goes through the wrapped store
+pub struct Timed(pub Arc<dyn ScoreStore>);
+
+impl Timed {
+    fn time<T>(&self, write: impl FnOnce(&dyn ScoreStore) -> T) -> T {
+        let start = Instant::now();
+        let result = write(&*self.0);
+        METRICS.persist.observe(start.elapsed());
+        result
+    }
+}
+
+impl ScoreStore for Timed {
+    fn load(&self, room: &str) -> io::Result<i32> {
+        self.0.load(room)
+    }
+
+    fn save(&self, room: &str, counter: i32) -> io::Result<()> {
+        self.time(|store| store.save(room, counter))
+    }
+
+    fn load_players(&self) -> io::Result<Vec<Player>> {
+        self.0.load_players()
+    }
+
+    fn save_players(&self, players: &[Player]) -> io::Result<()> {
+        self.time(|store| store.save_players(players))
+    }
+
+    fn load_replay(&self, id: &str) -> io::Result<Option<Replay>> {
+        self.0.load_replay(id)
+    }
+
+    fn save_replay(&self, id: &str, replay: &Replay) -> io::Result<()> {
+        self.time(|store| store.save_replay(id, replay))
+    }
+
+    fn load_todos(&self, list: &str) -> io::Result<Todos> {
+        self.0.load_todos(list)
+    }
+
+    fn save_todos(&self, list: &str, todos: &Todos) -> io::Result<()> {
+        self.time(|store| store.save_todos(list, todos))
+    }
+
+    fn load_account(&self, name: &str) -> io::Result<Option<String>> {
+        self.0.load_account(name)
+    }
+
+    fn save_account(&self, name: &str, hash: &str) -> io::Result<()> {
+        self.time(|store| store.save_account(name, hash))
+    }
+
+    fn append_history(&self, room: &str, samples: &[Sample]) -> io::Result<()> {
+        self.time(|store| store.append_history(room, samples))
+    }
+
+    fn load_history(&self, room: &str, from: u64, to: u64) -> io::Result<Vec<Sample>> {
+        self.0.load_history(room, from, to)
+    }
+
+    fn replace_history(&self, room: &str, before: u64, samples: &[Sample]) -> io::Result<()> {
+        self.time(|store| store.replace_history(room, before, samples))
+    }
+
+    fn history_rooms(&self) -> io::Result<Vec<String>> {
+        self.0.history_rooms()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_prometheus_text() {
+        let metrics = Metrics {
+            messages_received: AtomicU64::new(3),
+            broadcasts_sent: AtomicU64::new(0),
+            broadcast_failures: AtomicU64::new(0),
+            persist: Histogram::new(),
+        };
+        metrics.persist.observe(Duration::from_micros(700));
+        metrics.persist.observe(Duration::from_secs(3));
+        let output = render(&metrics, 2, &[("score".to_string(), 5)]);
+        assert!(output.contains("server_sessions 2\n"));
+        assert!(output.contains("# TYPE server_messages_received_total counter\nserver_messages_received_total 3\n"));
+        assert!(output.contains("server_persist_seconds_bucket{le=\"0.0005\"} 0\n"));
+        assert!(output.contains("server_persist_seconds_bucket{le=\"0.001\"} 1\n"));
+        assert!(output.contains("server_persist_seconds_bucket{le=\"2.5\"} 1\n"));
+        assert!(output.contains("server_persist_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(output.contains("server_persist_seconds_sum 3.0007\n"));
+        assert!(output.contains("server_persist_seconds_count 2\n"));
+        assert!(output.contains("server_counter{room=\"score\"} 5\n"));
+    }
+}
@@ -0,0 +1,186 @@
+use crate::{chat, leaderboard::Player, session::SessionId, todo::Entry, wire::Encoding};
+use serde::{Deserialize, Serialize};
+use snake_rules::Position;
+use serde_json::Value;
+
+pub const VERSION: u64 = 1;
+
+#[derive(Debug, Deserialize)]
+pub struct ClientFrame {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub message: ClientMessage,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Increment,
+    Query,
+    Subscribe,
+    Play { difficulty: u8 },
+    Direction { direction: [i32; 3] },
+    JoinArena,
+    LeaveArena,
+    Presence,
+    Watch { list: String },
+    Chat { text: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Welcome { session: SessionId, token: String, resumed: bool, counter: i32 },
+    Ack { seq: u64, counter: i32 },
+    Update { counter: i32, player: Option<String> },
+    Leaderboard { players: Vec<Player> },
+    State { player: Option<String>, tick: i32, run: u32, snake: Vec<Position>, food: Vec<[i32; 3]> },
+    Started { seq: u64, replay: String },
+    Joined { seq: u64, id: u32, size: [i32; 3] },
+    Arena { tick: i32, snakes: Vec<ArenaSnake>, food: Vec<[i32; 3]> },
+    Presence { seq: u64, room: usize, total: usize },
+    PlayerJoined { session: SessionId, player: Option<String>, room: usize, total: usize },
+    PlayerLeft { session: SessionId, player: Option<String>, room: usize, total: usize },
+    Chat(chat::Line),
+    ChatHistory { lines: Vec<chat::Line> },
+    Announcement { text: String },
+    Todos { seq: Option<u64>, list: String, entries: Vec<Entry> },
+    Error { seq: Option<u64>, code: ErrorCode, message: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ArenaSnake {
+    pub id: u32,
+    pub player: Option<String>,
+    pub run: u32,
+    pub parts: Vec<Position>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    Malformed,
+    UnsupportedVersion,
+    UnknownMessage,
+    UnsupportedFrame,
+    InvalidDirection,
+    NotPlaying,
+    RateLimited,
+    InvalidList,
+    Unauthorized,
+    InvalidChat,
+}
+
+#[derive(Serialize)]
+struct ServerFrame<'a> {
+    version: u64,
+    #[serde(flatten)]
+    message: &'a ServerMessage,
+}
+
+impl ClientMessage {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Increment => "increment",
+            Self::Query => "query",
+            Self::Subscribe => "subscribe",
+            Self::Play { .. } => "play",
+            Self::Direction { .. } => "direction",
+            Self::JoinArena => "join_arena",
+            Self::LeaveArena => "leave_arena",
+            Self::Presence => "presence",
+            Self::Watch { .. } => "watch",
+            Self::Chat { .. } => "chat",
+        }
+    }
+
+    // anonymous sessions may only send messages that change nothing
+    pub fn writes(&self) -> bool {
+        matches!(self, Self::Increment | Self::Play { .. } | Self::Direction { .. } | Self::JoinArena | Self::LeaveArena | Self::Chat { .. })
+    }
+}
+
+impl ServerMessage {
+    pub fn error(seq: Option<u64>, code: ErrorCode, message: impl ToString) -> Self {
+        Self::Error { seq, code, message: message.to_string() }
+    }
+
+    pub fn encode(&self) -> String {
+        serde_json::to_string(&ServerFrame { version: VERSION, message: self }).unwrap()
+    }
+
+    // MessagePack maps with the same field names as the JSON
+    pub fn encode_binary(&self) -> Vec<u8> {
+        rmp_serde::to_vec_named(&ServerFrame { version: VERSION, message: self }).unwrap()
+    }
+}
+
+pub fn decode(text: &str) -> Result<ClientFrame, ServerMessage> {
+    let value: Value = serde_json::from_str(text)
+        .map_err(|error| ServerMessage::error(None, ErrorCode::Malformed, error))?;
+    validate(value)
+}
+
+// binary frames are only understood once the session negotiated MessagePack
+pub fn decode_binary(bytes: &[u8], encoding: Encoding) -> Result<ClientFrame, ServerMessage> {
+    if encoding != Encoding::MessagePack {
+        return Err(ServerMessage::error(None, ErrorCode::UnsupportedFrame, "binary frames need the msgpack encoding"));
+    }
+    let value: Value = rmp_serde::from_slice(bytes)
+        .map_err(|error| ServerMessage::error(None, ErrorCode::Malformed, error))?;
+    validate(value)
+}
+
+fn validate(value: Value) -> Result<ClientFrame, ServerMessage> {
+    let seq = value.get("seq").and_then(Value::as_u64);
+    match value.get("version").and_then(Value::as_u64) {
+        Some(VERSION) => {}
+        Some(version) => return Err(ServerMessage::error(seq, ErrorCode::UnsupportedVersion, format!("unsupported version {version}, expected {VERSION}"))),
+        None => return Err(ServerMessage::error(seq, ErrorCode::Malformed, "missing version")),
+    }
+    if seq.is_none() {
+        return Err(ServerMessage::error(None, ErrorCode::Malformed, "missing seq"));
+    }
+    serde_json::from_value(value).map_err(|error| ServerMessage::error(seq, ErrorCode::UnknownMessage, error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejected(result: Result<ClientFrame, ServerMessage>) -> (Option<u64>, ErrorCode) {
+        match result {
+            Err(ServerMessage::Error { seq, code, .. }) => (seq, code),
+            other => panic!("expected an error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn malformed_json_is_rejected() {
+        assert_eq!(rejected(decode("{\"version\":1,")), (None, ErrorCode::Malformed));
+        assert_eq!(rejected(decode("[1, 2]")), (None, ErrorCode::Malformed));
+        assert_eq!(rejected(decode(r#"{"version":1,"type":"query"}"#)), (None, ErrorCode::Malformed));
+    }
+
+    #[test]
+    fn versions_other_than_the_current_one_are_rejected() {
+        assert_eq!(rejected(decode(r#"{"version":2,"seq":4,"type":"query"}"#)), (Some(4), ErrorCode::UnsupportedVersion));
+        assert_eq!(rejected(decode(r#"{"seq":4,"type":"query"}"#)), (Some(4), ErrorCode::Malformed));
+    }
+
+    #[test]
+    fn unknown_types_and_fields_are_rejected() {
+        assert_eq!(rejected(decode(r#"{"version":1,"seq":5,"type":"teleport"}"#)), (Some(5), ErrorCode::UnknownMessage));
+        assert_eq!(rejected(decode(r#"{"version":1,"seq":6,"type":"play","difficulty":"hard"}"#)), (Some(6), ErrorCode::UnknownMessage));
+        assert_eq!(rejected(decode(r#"{"version":1,"seq":7}"#)), (Some(7), ErrorCode::UnknownMessage));
+        assert_eq!(decode(r#"{"version":1,"seq":8,"type":"query"}"#).unwrap().message, ClientMessage::Query);
+    }
+
+    #[test]
+    fn binary_frames_need_the_msgpack_encoding() {
+        let bytes = rmp_serde::to_vec_named(&serde_json::json!({ "version": 1, "seq": 9, "type": "query" })).unwrap();
+        assert_eq!(rejected(decode_binary(&bytes, Encoding::Json)), (None, ErrorCode::UnsupportedFrame));
+        assert_eq!(decode_binary(&bytes, Encoding::MessagePack).unwrap().seq, 9);
+        assert_eq!(rejected(decode_binary(b"\xc1", Encoding::MessagePack)), (None, ErrorCode::Malformed));
+    }
+}
@@ -0,0 +1,162 @@
+use std::{collections::HashMap, net::IpAddr, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}, time::{Duration, Instant}};
+use tokio::sync::Notify;
+
+pub type SessionId = u64;
+
+pub struct Opened {
+    pub id: SessionId,
+    pub token: String,
+    pub subscribed: bool,
+    pub resumed: bool,
+    // notified when an admin kicks the session
+    pub kick: Arc<Notify>,
+}
+
+// what the admin API gets to see of an open connection
+#[derive(Debug, Clone)]
+pub struct Connected {
+    pub room: String,
+    pub player: Option<String>,
+    pub peer: Option<IpAddr>,
+    // seconds since the epoch
+    pub since: u64,
+}
+
+enum State {
+    Open { connected: Connected, kick: Arc<Notify>, kicked: bool },
+    Closed { expires: Instant },
+}
+
+struct Resumable {
+    id: SessionId,
+    subscribed: bool,
+    state: State,
+}
+
+pub struct Registry {
+    next: AtomicU64,
+    tokens: Mutex<HashMap<String, Resumable>>,
+    resume_window: Duration,
+}
+
+impl Registry {
+    pub fn new(resume_window: Duration) -> Self {
+        Self {
+            next: AtomicU64::new(0),
+            tokens: Mutex::new(HashMap::new()),
+            resume_window,
+        }
+    }
+
+    // hands out a fresh token, taking over the identity behind `resume` if it is closed and not expired,
+    // a token that is still connected starts a new session instead so the old connection stays counted
+    pub fn open(&self, resume: Option<&str>, connected: Connected) -> Opened {
+        let now = Instant::now();
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.retain(|_, resumable| match resumable.state {
+            State::Open { .. } => true,
+            State::Closed { expires } => expires > now,
+        });
+        let token = format!("{:032x}", rand::random::<u128>());
+        let kick = Arc::new(Notify::new());
+        let resumable = resume
+            .filter(|resume| matches!(tokens.get(*resume), Some(Resumable { state: State::Closed { .. }, .. })))
+            .and_then(|resume| tokens.remove(resume));
+        let opened = match resumable {
+            Some(resumable) => Opened { id: resumable.id, token, subscribed: resumable.subscribed, resumed: true, kick },
+            None => Opened { id: self.next.fetch_add(1, Ordering::Relaxed), token, subscribed: false, resumed: false, kick },
+        };
+        tokens.insert(opened.token.clone(), Resumable {
+            id: opened.id,
+            subscribed: opened.subscribed,
+            state: State::Open { connected, kick: Arc::clone(&opened.kick), kicked: false },
+        });
+        opened
+    }
+
+    pub fn live(&self) -> usize {
+        self.tokens.lock().unwrap().values().filter(|resumable| matches!(resumable.state, State::Open { .. })).count()
+    }
+
+    pub fn connected(&self) -> Vec<(SessionId, Connected)> {
+        let mut connected: Vec<_> = self.tokens.lock().unwrap().values()
+            .filter_map(|resumable| match &resumable.state {
+                State::Open { connected, .. } => Some((resumable.id, connected.clone())),
+                State::Closed { .. } => None,
+            })
+            .collect();
+        connected.sort_by_key(|(id, connected)| (*id, connected.since));
+        connected
+    }
+
+    // closes every connection of the session for good, returns false if none was open
+    pub fn kick(&self, id: SessionId) -> bool {
+        let mut kicked_any = false;
+        for resumable in self.tokens.lock().unwrap().values_mut().filter(|resumable| resumable.id == id) {
+            if let State::Open { kick, kicked, .. } = &mut resumable.state {
+                *kicked = true;
+                kick.notify_one();
+                kicked_any = true;
+            }
+        }
+        kicked_any
+    }
+
+    // keeps the session resumable for a while unless it was kicked, returns false if another connection already resumed it
+    pub fn close(&self, token: &str, subscribed: bool) -> bool {
+        let mut tokens = self.tokens.lock().unwrap();
+        let Some(resumable) = tokens.get_mut(token) else { return false };
+        if let State::Open { kicked: true, .. } = resumable.state {
+            tokens.remove(token);
+            return true;
+        }
+        resumable.subscribed = subscribed;
+        resumable.state = State::Closed { expires: Instant::now() + self.resume_window };
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connected(room: &str) -> Connected {
+        Connected { room: room.to_string(), player: None, peer: None, since: 0 }
+    }
+
+    #[test]
+    fn closed_sessions_can_be_resumed() {
+        let registry = Registry::new(Duration::from_secs(60));
+        let first = registry.open(None, connected("score"));
+        assert!(registry.close(&first.token, true));
+        assert_eq!(registry.live(), 0);
+        let resumed = registry.open(Some(&first.token), connected("score"));
+        assert_eq!((resumed.id, resumed.subscribed, resumed.resumed), (first.id, true, true));
+        assert!(!registry.close(&first.token, false));
+    }
+
+    #[test]
+    fn kicked_sessions_are_gone_for_good() {
+        let registry = Registry::new(Duration::from_secs(60));
+        let first = registry.open(None, connected("score"));
+        let second = registry.open(None, connected("other"));
+        assert_eq!(registry.connected().iter().map(|(id, connected)| (*id, connected.room.as_str())).collect::<Vec<_>>(), [(first.id, "score"), (second.id, "other")]);
+        assert!(registry.kick(first.id));
+        assert!(registry.close(&first.token, true));
+        assert!(!registry.kick(first.id));
+        assert!(!registry.open(Some(&first.token), connected("score")).resumed);
+    }
+
+    #[test]
+    fn open_sessions_cannot_be_resumed() {
+        let registry = Registry::new(Duration::from_secs(60));
+        let first = registry.open(None, connected("score"));
+        let second = registry.open(Some(&first.token), connected("score"));
+        assert!(!second.resumed);
+        assert_ne!(second.id, first.id);
+        assert_eq!(registry.live(), 2);
+        assert!(registry.kick(first.id));
+        assert!(registry.close(&first.token, false));
+        assert_eq!(registry.live(), 1);
+    }
+}
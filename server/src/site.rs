@@ -0,0 +1,82 @@
+use actix_files::{Files, NamedFile};
+use actix_web::{dev::{fn_service, Service as _, ServiceRequest, ServiceResponse}, http::{header, Method}, web, HttpResponse};
+use std::path::{Path, PathBuf};
+
+// serves the built Angular bundle, anything that looks like a client side route gets index.html
+pub fn configure(config: &mut web::ServiceConfig, directory: &Path) {
+    let index = directory.join("index.html");
+    config.service(web::scope("")
+        .wrap_fn(|req, service| {
+            let path = req.path().to_string();
+            let response = service.call(req);
+            async move {
+                let mut response = response.await?;
+                if response.status().is_success() {
+                    let html = response.headers().get(header::CONTENT_TYPE).is_some_and(|kind| kind.as_bytes().starts_with(b"text/html"));
+                    response.headers_mut().insert(header::CACHE_CONTROL, header::HeaderValue::from_static(cache_control(&path, html)));
+                }
+                Ok(response)
+            }
+        })
+        .service(Files::new("/", directory)
+            .index_file("index.html")
+            .default_handler(fn_service(move |req| fallback(index.clone(), req)))));
+}
+
+async fn fallback(index: PathBuf, req: ServiceRequest) -> actix_web::Result<ServiceResponse> {
+    let (req, _) = req.into_parts();
+    let file = req.path().rsplit('/').next().is_some_and(|name| name.contains('.'));
+    let page = matches!(*req.method(), Method::GET | Method::HEAD) && !req.path().starts_with("/api/") && !file;
+    let response = match page {
+        true => NamedFile::open_async(index).await?.into_response(&req),
+        false => HttpResponse::NotFound().finish(),
+    };
+    Ok(ServiceResponse::new(req, response))
+}
+
+// Angular puts an 8 character hash in bundle names like main-ABCD1234.js, those never change
+fn cache_control(path: &str, html: bool) -> &'static str {
+    let name = path.rsplit('/').next().unwrap_or_default();
+    let hashed = name.split('.').next().and_then(|stem| stem.rsplit_once('-'))
+        .is_some_and(|(_, hash)| hash.len() == 8 && hash.chars().all(|character| character.is_ascii_uppercase() || character.is_ascii_digit()));
+    match (html, hashed) {
+        (true, _) => "no-cache",
+        (false, true) => "public, max-age=31536000, immutable",
+        (false, false) => "public, max-age=3600",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_hashed_bundles_are_immutable() {
+        assert_eq!(cache_control("/main-4GQCXGQ6.js", false), "public, max-age=31536000, immutable");
+        assert_eq!(cache_control("/media/styles-Z7PX5LDB.css", false), "public, max-age=31536000, immutable");
+        assert_eq!(cache_control("/favicon.ico", false), "public, max-age=3600");
+        assert_eq!(cache_control("/chunk-short.js", false), "public, max-age=3600");
+        assert_eq!(cache_control("/", true), "no-cache");
+        assert_eq!(cache_control("/snake", true), "no-cache");
+    }
+
+    #[actix_web::test]
+    async fn client_routes_get_the_page_and_everything_else_keeps_its_own() {
+        use actix_web::test::{call_service, init_service, read_body, TestRequest};
+        let (data, site) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        std::fs::write(site.path().join("index.html"), "<html>snake</html>").unwrap();
+        let config = crate::config::Config { data: data.path().to_path_buf(), site: Some(site.path().to_path_buf()), ..Default::default() };
+        let app = init_service(crate::app(web::Data::new(crate::Counter::new(config).unwrap()))).await;
+
+        let page = call_service(&app, TestRequest::get().uri("/snake").to_request()).await;
+        assert_eq!(page.headers().get(header::CACHE_CONTROL).unwrap(), "no-cache");
+        assert_eq!(read_body(page).await, "<html>snake</html>");
+        assert_eq!(call_service(&app, TestRequest::get().uri("/missing.js").to_request()).await.status(), 404);
+        assert_eq!(call_service(&app, TestRequest::get().uri("/api/nope").to_request()).await.status(), 404);
+
+        let score = call_service(&app, TestRequest::get().uri("/api/score").to_request()).await;
+        assert_eq!(read_body(score).await, r#"{"room":"score","counter":0}"#);
+        let echo = call_service(&app, TestRequest::get().uri("/echo").to_request()).await;
+        assert_eq!(echo.status(), 400, "a plain request to the websocket route is refused rather than served the page");
+    }
+}
@@ -0,0 +1,487 @@
+use crate::{history::Sample, leaderboard::Player, todo::{Entry, Todos}};
+use rusqlite::{Connection, OptionalExtension};
+use snake_rules::Replay;
+use std::{collections::BTreeMap, io, path::{Path, PathBuf}, sync::{Arc, Mutex}};
+
+pub trait ScoreStore: Send + Sync {
+    fn load(&self, room: &str) -> io::Result<i32>;
+    fn save(&self, room: &str, counter: i32) -> io::Result<()>;
+    fn load_players(&self) -> io::Result<Vec<Player>>;
+    fn save_players(&self, players: &[Player]) -> io::Result<()>;
+    fn load_replay(&self, id: &str) -> io::Result<Option<Replay>>;
+    fn save_replay(&self, id: &str, replay: &Replay) -> io::Result<()>;
+    fn load_todos(&self, list: &str) -> io::Result<Todos>;
+    fn save_todos(&self, list: &str, todos: &Todos) -> io::Result<()>;
+    // password hashes by player name
+    fn load_account(&self, name: &str) -> io::Result<Option<String>>;
+    fn save_account(&self, name: &str, hash: &str) -> io::Result<()>;
+    // counter samples in time order, `from` inclusive and `to` exclusive
+    fn append_history(&self, room: &str, samples: &[Sample]) -> io::Result<()>;
+    fn load_history(&self, room: &str, from: u64, to: u64) -> io::Result<Vec<Sample>>;
+    // swaps every sample before `before` for the given ones
+    fn replace_history(&self, room: &str, before: u64, samples: &[Sample]) -> io::Result<()>;
+    fn history_rooms(&self) -> io::Result<Vec<String>>;
+}
+
+pub fn open(kind: &str, directory: impl AsRef<Path>) -> io::Result<Arc<dyn ScoreStore>> {
+    match kind {
+        "file" => Ok(Arc::new(FileStore::new(directory)?)),
+        "sqlite" => Ok(Arc::new(SqliteStore::new(directory.as_ref().join("score.sqlite3"))?)),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown store {kind:?}, expected \"file\" or \"sqlite\""))),
+    }
+}
+
+pub struct FileStore {
+    directory: PathBuf,
+    // appends and compaction both touch the same history file
+    history: Mutex<()>,
+}
+
+impl FileStore {
+    pub fn new(directory: impl AsRef<Path>) -> io::Result<Self> {
+        std::fs::create_dir_all(&directory)?;
+        Ok(Self { directory: directory.as_ref().to_path_buf(), history: Mutex::new(()) })
+    }
+
+    fn history_path(&self, room: &str) -> PathBuf {
+        self.directory.join("history").join(format!("{room}.jsonl"))
+    }
+
+    fn history(&self, room: &str) -> io::Result<Vec<Sample>> {
+        match std::fs::read_to_string(self.history_path(room)) {
+            Ok(content) => content.lines()
+                .map(|line| serde_json::from_str(line).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)))
+                .collect(),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn lines(samples: &[Sample]) -> io::Result<Vec<u8>> {
+        let mut content = vec![];
+        for sample in samples {
+            serde_json::to_writer(&mut content, sample)?;
+            content.push(b'\n');
+        }
+        Ok(content)
+    }
+
+    fn path(&self, room: &str) -> PathBuf {
+        self.directory.join(format!("{room}.txt"))
+    }
+
+    fn accounts(&self) -> io::Result<BTreeMap<String, String>> {
+        match std::fs::read(self.directory.join("accounts.json")) {
+            Ok(content) => serde_json::from_slice(&content).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn write(&self, path: PathBuf, content: &[u8]) -> io::Result<()> {
+        let temporary = temporary(&path);
+        let mut file = std::fs::File::create(&temporary)?;
+        io::Write::write_all(&mut file, content)?;
+        file.sync_all()?;
+        std::fs::rename(temporary, path)
+    }
+}
+
+// appends to the whole file name, so players.txt and players.json never share a temporary file
+fn temporary(path: &Path) -> PathBuf {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    PathBuf::from(temporary)
+}
+
+impl ScoreStore for FileStore {
+    fn load(&self, room: &str) -> io::Result<i32> {
+        match std::fs::read_to_string(self.path(room)) {
+            Ok(content) => content.trim().parse().map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn save(&self, room: &str, counter: i32) -> io::Result<()> {
+        self.write(self.path(room), format!("{counter}").as_bytes())
+    }
+
+    fn load_players(&self) -> io::Result<Vec<Player>> {
+        match std::fs::read(self.directory.join("players.json")) {
+            Ok(content) => serde_json::from_slice(&content).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn save_players(&self, players: &[Player]) -> io::Result<()> {
+        self.write(self.directory.join("players.json"), &serde_json::to_vec(players)?)
+    }
+
+    fn load_replay(&self, id: &str) -> io::Result<Option<Replay>> {
+        match std::fs::read(self.directory.join("replays").join(format!("{id}.json"))) {
+            Ok(content) => serde_json::from_slice(&content).map(Some).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn save_replay(&self, id: &str, replay: &Replay) -> io::Result<()> {
+        let directory = self.directory.join("replays");
+        std::fs::create_dir_all(&directory)?;
+        self.write(directory.join(format!("{id}.json")), &serde_json::to_vec(replay)?)
+    }
+
+    fn load_todos(&self, list: &str) -> io::Result<Todos> {
+        // older lists were saved as a bare array of entries
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum Saved {
+            Todos(Todos),
+            Entries(Vec<Entry>),
+        }
+        match std::fs::read(self.directory.join("todos").join(format!("{list}.json"))) {
+            Ok(content) => match serde_json::from_slice(&content).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))? {
+                Saved::Todos(todos) => Ok(todos),
+                Saved::Entries(entries) => Ok(entries.into()),
+            },
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Todos::default()),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn save_todos(&self, list: &str, todos: &Todos) -> io::Result<()> {
+        let directory = self.directory.join("todos");
+        std::fs::create_dir_all(&directory)?;
+        self.write(directory.join(format!("{list}.json")), &serde_json::to_vec(todos)?)
+    }
+
+    fn load_account(&self, name: &str) -> io::Result<Option<String>> {
+        Ok(self.accounts()?.remove(name))
+    }
+
+    fn save_account(&self, name: &str, hash: &str) -> io::Result<()> {
+        let mut accounts = self.accounts()?;
+        accounts.insert(name.to_string(), hash.to_string());
+        self.write(self.directory.join("accounts.json"), &serde_json::to_vec(&accounts)?)
+    }
+
+    fn append_history(&self, room: &str, samples: &[Sample]) -> io::Result<()> {
+        let _history = self.history.lock().unwrap();
+        std::fs::create_dir_all(self.directory.join("history"))?;
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(self.history_path(room))?;
+        io::Write::write_all(&mut file, &Self::lines(samples)?)?;
+        file.sync_data()
+    }
+
+    fn load_history(&self, room: &str, from: u64, to: u64) -> io::Result<Vec<Sample>> {
+        let _history = self.history.lock().unwrap();
+        Ok(self.history(room)?.into_iter().filter(|sample| (from..to).contains(&sample.at)).collect())
+    }
+
+    fn replace_history(&self, room: &str, before: u64, samples: &[Sample]) -> io::Result<()> {
+        let _history = self.history.lock().unwrap();
+        let mut kept = samples.to_vec();
+        kept.extend(self.history(room)?.into_iter().filter(|sample| sample.at >= before));
+        std::fs::create_dir_all(self.directory.join("history"))?;
+        self.write(self.history_path(room), &Self::lines(&kept)?)
+    }
+
+    fn history_rooms(&self) -> io::Result<Vec<String>> {
+        let entries = match std::fs::read_dir(self.directory.join("history")) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(error) => return Err(error),
+        };
+        let mut rooms = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "jsonl") {
+                rooms.extend(path.file_stem().and_then(|stem| stem.to_str()).map(str::to_string));
+            }
+        }
+        rooms.sort();
+        Ok(rooms)
+    }
+}
+
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+        if let Some(parent) = path.as_ref().parent() { std::fs::create_dir_all(parent)?; }
+        let connection = Connection::open(path).map_err(io::Error::other)?;
+        connection.execute_batch("
+            PRAGMA journal_mode = WAL;
+            CREATE TABLE IF NOT EXISTS scores (room TEXT PRIMARY KEY, counter INTEGER NOT NULL);
+            CREATE TABLE IF NOT EXISTS players (name TEXT PRIMARY KEY, total INTEGER NOT NULL, best INTEGER NOT NULL);
+            CREATE TABLE IF NOT EXISTS replays (id TEXT PRIMARY KEY, replay TEXT NOT NULL);
+            CREATE TABLE IF NOT EXISTS accounts (name TEXT PRIMARY KEY, hash TEXT NOT NULL);
+            CREATE TABLE IF NOT EXISTS todos (list TEXT NOT NULL, id INTEGER NOT NULL, done INTEGER NOT NULL, content TEXT NOT NULL, PRIMARY KEY (list, id));
+            CREATE TABLE IF NOT EXISTS todo_lists (list TEXT PRIMARY KEY, next INTEGER NOT NULL);
+            CREATE TABLE IF NOT EXISTS history (room TEXT NOT NULL, at INTEGER NOT NULL, counter INTEGER NOT NULL, changes INTEGER NOT NULL);
+            CREATE INDEX IF NOT EXISTS history_by_time ON history (room, at);
+        ").map_err(io::Error::other)?;
+        Ok(Self { connection: Mutex::new(connection) })
+    }
+}
+
+impl ScoreStore for SqliteStore {
+    fn load(&self, room: &str) -> io::Result<i32> {
+        let connection = self.connection.lock().unwrap();
+        let counter = connection
+            .query_row("SELECT counter FROM scores WHERE room = ?1", [room], |row| row.get(0))
+            .optional()
+            .map_err(io::Error::other)?;
+        Ok(counter.unwrap_or(0))
+    }
+
+    fn save(&self, room: &str, counter: i32) -> io::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT INTO scores (room, counter) VALUES (?1, ?2) ON CONFLICT (room) DO UPDATE SET counter = excluded.counter",
+            rusqlite::params![room, counter],
+        ).map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    fn load_players(&self) -> io::Result<Vec<Player>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare("SELECT name, total, best FROM players").map_err(io::Error::other)?;
+        let players = statement
+            .query_map([], |row| Ok(Player { name: row.get(0)?, total: row.get(1)?, best: row.get(2)? }))
+            .and_then(|rows| rows.collect())
+            .map_err(io::Error::other)?;
+        Ok(players)
+    }
+
+    fn save_players(&self, players: &[Player]) -> io::Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(io::Error::other)?;
+        for player in players {
+            transaction.execute(
+                "INSERT INTO players (name, total, best) VALUES (?1, ?2, ?3) ON CONFLICT (name) DO UPDATE SET total = excluded.total, best = excluded.best",
+                rusqlite::params![player.name, player.total, player.best],
+            ).map_err(io::Error::other)?;
+        }
+        transaction.commit().map_err(io::Error::other)
+    }
+
+    fn load_replay(&self, id: &str) -> io::Result<Option<Replay>> {
+        let connection = self.connection.lock().unwrap();
+        let replay: Option<String> = connection
+            .query_row("SELECT replay FROM replays WHERE id = ?1", [id], |row| row.get(0))
+            .optional()
+            .map_err(io::Error::other)?;
+        replay.map(|replay| serde_json::from_str(&replay).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))).transpose()
+    }
+
+    fn save_replay(&self, id: &str, replay: &Replay) -> io::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT INTO replays (id, replay) VALUES (?1, ?2) ON CONFLICT (id) DO UPDATE SET replay = excluded.replay",
+            rusqlite::params![id, serde_json::to_string(replay)?],
+        ).map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    fn load_todos(&self, list: &str) -> io::Result<Todos> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare("SELECT id, done, content FROM todos WHERE list = ?1 ORDER BY id").map_err(io::Error::other)?;
+        let entries: Vec<Entry> = statement
+            .query_map([list], |row| Ok(Entry { id: row.get(0)?, done: row.get(1)?, content: row.get(2)? }))
+            .and_then(|rows| rows.collect())
+            .map_err(io::Error::other)?;
+        let next = connection
+            .query_row("SELECT next FROM todo_lists WHERE list = ?1", [list], |row| row.get(0))
+            .optional()
+            .map_err(io::Error::other)?;
+        // lists saved before the counter existed continue after their highest id
+        Ok(match next {
+            Some(next) => Todos { next, entries },
+            None => entries.into(),
+        })
+    }
+
+    fn save_todos(&self, list: &str, todos: &Todos) -> io::Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(io::Error::other)?;
+        transaction.execute("DELETE FROM todos WHERE list = ?1", [list]).map_err(io::Error::other)?;
+        transaction.execute(
+            "INSERT OR REPLACE INTO todo_lists (list, next) VALUES (?1, ?2)",
+            rusqlite::params![list, todos.next],
+        ).map_err(io::Error::other)?;
+        for entry in &todos.entries {
+            transaction.execute(
+                "INSERT INTO todos (list, id, done, content) VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![list, entry.id, entry.done, entry.content],
+            ).map_err(io::Error::other)?;
+        }
+        transaction.commit().map_err(io::Error::other)
+    }
+
+    fn load_account(&self, name: &str) -> io::Result<Option<String>> {
+        let connection = self.connection.lock().unwrap();
+        connection
+            .query_row("SELECT hash FROM accounts WHERE name = ?1", [name], |row| row.get(0))
+            .optional()
+            .map_err(io::Error::other)
+    }
+
+    fn save_account(&self, name: &str, hash: &str) -> io::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT INTO accounts (name, hash) VALUES (?1, ?2) ON CONFLICT (name) DO UPDATE SET hash = excluded.hash",
+            rusqlite::params![name, hash],
+        ).map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    fn append_history(&self, room: &str, samples: &[Sample]) -> io::Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(io::Error::other)?;
+        insert_history(&transaction, room, samples)?;
+        transaction.commit().map_err(io::Error::other)
+    }
+
+    fn load_history(&self, room: &str, from: u64, to: u64) -> io::Result<Vec<Sample>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare("SELECT at, counter, changes FROM history WHERE room = ?1 AND at >= ?2 AND at < ?3 ORDER BY at, rowid")
+            .map_err(io::Error::other)?;
+        // sqlite integers are signed, clamping keeps u64::MAX usable as an open end
+        let (from, to) = (from.min(i64::MAX as u64) as i64, to.min(i64::MAX as u64) as i64);
+        let samples = statement
+            .query_map(rusqlite::params![room, from, to], |row| Ok(Sample { at: row.get::<_, i64>(0)? as u64, counter: row.get(1)?, changes: row.get(2)? }))
+            .and_then(|rows| rows.collect())
+            .map_err(io::Error::other)?;
+        Ok(samples)
+    }
+
+    fn replace_history(&self, room: &str, before: u64, samples: &[Sample]) -> io::Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(io::Error::other)?;
+        transaction.execute("DELETE FROM history WHERE room = ?1 AND at < ?2", rusqlite::params![room, before.min(i64::MAX as u64) as i64]).map_err(io::Error::other)?;
+        insert_history(&transaction, room, samples)?;
+        transaction.commit().map_err(io::Error::other)
+    }
+
+    fn history_rooms(&self) -> io::Result<Vec<String>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare("SELECT DISTINCT room FROM history ORDER BY room").map_err(io::Error::other)?;
+        let rooms = statement.query_map([], |row| row.get(0)).and_then(|rows| rows.collect()).map_err(io::Error::other)?;
+        Ok(rooms)
+    }
+}
+
+fn insert_history(transaction: &rusqlite::Transaction, room: &str, samples: &[Sample]) -> io::Result<()> {
+    for sample in samples {
+        transaction.execute(
+            "INSERT INTO history (room, at, counter, changes) VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![room, sample.at as i64, sample.counter, sample.changes],
+        ).map_err(io::Error::other)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temporary_files_keep_the_extension() {
+        assert_eq!(temporary(Path::new("data/players.json")), Path::new("data/players.json.tmp"));
+        assert_ne!(temporary(Path::new("data/players.txt")), temporary(Path::new("data/players.json")));
+    }
+
+    fn round_trip(store: &dyn ScoreStore) {
+        assert_eq!(store.load("score").unwrap(), 0);
+        store.save("score", 3).unwrap();
+        store.save("other", -1).unwrap();
+        store.save("score", 4).unwrap();
+        assert_eq!(store.load("score").unwrap(), 4);
+        assert_eq!(store.load("other").unwrap(), -1);
+
+        assert_eq!(store.load_players().unwrap(), vec![]);
+        let player = Player { name: "ada".to_string(), total: 12, best: 5 };
+        store.save_players(std::slice::from_ref(&player)).unwrap();
+        assert_eq!(store.load_players().unwrap(), vec![player]);
+
+        assert_eq!(store.load_replay("0123456789abcdef").unwrap(), None);
+        let replay = Replay::new([10, 10, 1], 3, 25, 5);
+        store.save_replay("0123456789abcdef", &replay).unwrap();
+        assert_eq!(store.load_replay("0123456789abcdef").unwrap(), Some(replay));
+
+        assert_eq!(store.load_todos("home").unwrap(), Todos::default());
+        let entries = vec![Entry { id: 0, done: true, content: "milk".to_string() }, Entry { id: 2, done: false, content: "eggs".to_string() }];
+        store.save_todos("home", &Todos { next: 3, entries: entries.clone() }).unwrap();
+        store.save_todos("work", &Todos { next: 1, entries: entries[..1].to_vec() }).unwrap();
+        // the counter outlives the entry that had the highest id
+        store.save_todos("home", &Todos { next: 3, entries: entries[..1].to_vec() }).unwrap();
+        assert_eq!(store.load_todos("home").unwrap(), Todos { next: 3, entries: entries[..1].to_vec() });
+        assert_eq!(store.load_todos("work").unwrap(), Todos { next: 1, entries: entries[..1].to_vec() });
+
+        assert_eq!(store.load_account("ada").unwrap(), None);
+        store.save_account("ada", "hash").unwrap();
+        store.save_account("bob", "other").unwrap();
+        assert_eq!(store.load_account("ada").unwrap().as_deref(), Some("hash"));
+
+        assert_eq!(store.history_rooms().unwrap(), Vec::<String>::new());
+        let samples = [Sample { at: 10, counter: 1, changes: 1 }, Sample { at: 20, counter: 3, changes: 2 }, Sample { at: 30, counter: 4, changes: 1 }];
+        store.append_history("score", &samples[..2]).unwrap();
+        store.append_history("score", &samples[2..]).unwrap();
+        store.append_history("other", &samples[..1]).unwrap();
+        assert_eq!(store.load_history("score", 0, u64::MAX).unwrap(), samples);
+        assert_eq!(store.load_history("score", 20, 30).unwrap(), samples[1..2]);
+        store.replace_history("score", 30, &[Sample { at: 0, counter: 3, changes: 3 }]).unwrap();
+        assert_eq!(store.load_history("score", 0, u64::MAX).unwrap(), [Sample { at: 0, counter: 3, changes: 3 }, samples[2]]);
+        assert_eq!(store.history_rooms().unwrap(), ["other", "score"]);
+    }
+
+    #[test]
+    fn file_store_round_trip() {
+        let directory = tempfile::tempdir().unwrap();
+        round_trip(&FileStore::new(directory.path().join("data")).unwrap());
+        assert!(!directory.path().join("data/score.txt.tmp").exists());
+        assert_eq!(std::fs::read_to_string(directory.path().join("data/score.txt")).unwrap(), "4");
+    }
+
+    #[test]
+    fn file_store_rejects_corrupt_file() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(directory.path().join("score.txt"), "four").unwrap();
+        let store = FileStore::new(directory.path()).unwrap();
+        assert_eq!(store.load("score").unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn file_store_reads_todo_lists_saved_as_arrays() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::create_dir(directory.path().join("todos")).unwrap();
+        std::fs::write(directory.path().join("todos/home.json"), r#"[{"id":4,"done":false,"content":"milk"}]"#).unwrap();
+        let todos = FileStore::new(directory.path()).unwrap().load_todos("home").unwrap();
+        assert_eq!((todos.next, todos.entries.len()), (5, 1));
+    }
+
+    #[test]
+    fn sqlite_store_round_trip() {
+        let directory = tempfile::tempdir().unwrap();
+        round_trip(&SqliteStore::new(directory.path().join("score.sqlite3")).unwrap());
+    }
+
+    #[test]
+    fn sqlite_store_persists_across_reopen() {
+        let directory = tempfile::tempdir().unwrap();
+        open("sqlite", directory.path()).unwrap().save("score", 7).unwrap();
+        assert_eq!(open("sqlite", directory.path()).unwrap().load("score").unwrap(), 7);
+    }
+
+    #[test]
+    fn open_rejects_unknown_kind() {
+        let directory = tempfile::tempdir().unwrap();
+        assert!(open("redis", directory.path()).is_err());
+    }
+}
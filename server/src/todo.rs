@@ -0,0 +1,247 @@
+use crate::{auth::Authenticated, hub::Hub, protocol::ServerMessage, store::ScoreStore, valid_room_name, Counter};
+use actix_web::{error, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::{io, sync::{Arc, Mutex}};
+
+const MAX_CONTENT: usize = 1000;
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub id: u32,
+    pub done: bool,
+    pub content: String,
+}
+
+// the entries of a list and the id the next one gets, which only goes up so a deleted id is never handed out again
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct Todos {
+    pub next: u32,
+    pub entries: Vec<Entry>,
+}
+
+// lists saved before the counter existed continue after their highest id
+impl From<Vec<Entry>> for Todos {
+    fn from(entries: Vec<Entry>) -> Self {
+        Self { next: entries.iter().map(|entry| entry.id + 1).max().unwrap_or(0), entries }
+    }
+}
+
+impl Todos {
+    fn add(&mut self, content: String, done: bool) -> Entry {
+        let entry = Entry { id: self.next, done, content };
+        self.next += 1;
+        self.entries.push(entry.clone());
+        entry
+    }
+
+    fn edit(&mut self, id: u32, content: Option<String>, done: Option<bool>) -> Option<Entry> {
+        let entry = self.entries.iter_mut().find(|entry| entry.id == id)?;
+        if let Some(content) = content { entry.content = content; }
+        if let Some(done) = done { entry.done = done; }
+        Some(entry.clone())
+    }
+
+    fn remove(&mut self, id: u32) -> Option<Entry> {
+        let index = self.entries.iter().position(|entry| entry.id == id)?;
+        Some(self.entries.remove(index))
+    }
+
+    // every entry, or only the done ones
+    fn clear(&mut self, done: bool) {
+        self.entries.retain(|entry| done && !entry.done);
+    }
+}
+
+pub struct TodoList {
+    pub name: String,
+    store: Arc<dyn ScoreStore>,
+    todos: Mutex<Todos>,
+    writing: Mutex<()>,
+    pub watchers: Hub,
+}
+
+impl TodoList {
+    pub fn load(name: &str, store: Arc<dyn ScoreStore>) -> io::Result<Self> {
+        Ok(Self {
+            name: name.to_string(),
+            todos: Mutex::new(store.load_todos(name)?),
+            store,
+            writing: Mutex::new(()),
+            watchers: Hub::default(),
+        })
+    }
+
+    pub fn entries(&self) -> Vec<Entry> {
+        self.todos.lock().unwrap().entries.clone()
+    }
+
+    pub fn snapshot(&self, seq: Option<u64>) -> ServerMessage {
+        ServerMessage::Todos { seq, list: self.name.clone(), entries: self.entries() }
+    }
+
+    // applies a change to a copy and only keeps it once it is persisted, then pushes it to everyone watching,
+    // unless the change returned None
+    async fn update<T: Send + 'static>(self: &Arc<Self>, change: impl FnOnce(&mut Todos) -> Option<T> + Send + 'static) -> io::Result<Option<T>> {
+        let list = Arc::clone(self);
+        let result = web::block(move || -> io::Result<Option<T>> {
+            let _writing = list.writing.lock().unwrap();
+            let mut todos = list.todos.lock().unwrap().clone();
+            let Some(result) = change(&mut todos) else { return Ok(None) };
+            list.store.save_todos(&list.name, &todos)?;
+            *list.todos.lock().unwrap() = todos;
+            Ok(Some(result))
+        }).await.map_err(io::Error::other)??;
+        if result.is_some() { self.watchers.broadcast(&self.snapshot(None)); }
+        Ok(result)
+    }
+
+    pub fn persist(&self) -> io::Result<()> {
+        let _writing = self.writing.lock().unwrap();
+        let todos = self.todos.lock().unwrap().clone();
+        self.store.save_todos(&self.name, &todos)
+    }
+}
+
+fn valid_content(content: &str) -> bool {
+    !content.trim().is_empty() && content.chars().count() <= MAX_CONTENT
+}
+
+#[derive(Deserialize)]
+struct NewEntry {
+    content: String,
+    #[serde(default)]
+    done: bool,
+}
+
+#[derive(Deserialize)]
+struct Edit {
+    content: Option<String>,
+    done: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct Clear {
+    #[serde(default)]
+    done: bool,
+}
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config
+        .route("/todos/{list}", web::get().to(entries))
+        .route("/todos/{list}", web::post().to(add))
+        .route("/todos/{list}", web::delete().to(clear))
+        .route("/todos/{list}/{id}", web::patch().to(edit))
+        .route("/todos/{list}/{id}", web::delete().to(remove));
+}
+
+async fn open(data: &Counter, name: &str) -> actix_web::Result<Arc<TodoList>> {
+    if !valid_room_name(name) {
+        return Err(error::ErrorBadRequest("invalid list name"));
+    }
+    Ok(data.todo_list(name).await?)
+}
+
+async fn entries(data: web::Data<Counter>, name: web::Path<String>) -> actix_web::Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(open(&data, &name).await?.entries()))
+}
+
+async fn add(data: web::Data<Counter>, name: web::Path<String>, entry: web::Json<NewEntry>, _: Authenticated) -> actix_web::Result<HttpResponse> {
+    if !valid_content(&entry.content) {
+        return Err(error::ErrorBadRequest("content must be 1 to 1000 characters and not only whitespace"));
+    }
+    let list = open(&data, &name).await?;
+    let NewEntry { content, done } = entry.into_inner();
+    let entry = list.update(move |todos| Some(todos.add(content, done))).await?;
+    Ok(HttpResponse::Created().json(entry))
+}
+
+async fn edit(data: web::Data<Counter>, path: web::Path<(String, u32)>, edit: web::Json<Edit>, _: Authenticated) -> actix_web::Result<HttpResponse> {
+    let (name, id) = path.into_inner();
+    if edit.content.as_deref().is_some_and(|content| !valid_content(content)) {
+        return Err(error::ErrorBadRequest("content must be 1 to 1000 characters and not only whitespace"));
+    }
+    let list = open(&data, &name).await?;
+    let Edit { content, done } = edit.into_inner();
+    let entry = list.update(move |todos| todos.edit(id, content, done)).await?;
+    entry.map(|entry| HttpResponse::Ok().json(entry)).ok_or_else(|| error::ErrorNotFound("no such entry"))
+}
+
+async fn remove(data: web::Data<Counter>, path: web::Path<(String, u32)>, _: Authenticated) -> actix_web::Result<HttpResponse> {
+    let (name, id) = path.into_inner();
+    let list = open(&data, &name).await?;
+    let removed = list.update(move |todos| todos.remove(id)).await?;
+    removed.map(|_| HttpResponse::NoContent().finish()).ok_or_else(|| error::ErrorNotFound("no such entry"))
+}
+
+// removes every entry, or only the done ones with `?done=true`
+async fn clear(data: web::Data<Counter>, name: web::Path<String>, clear: web::Query<Clear>, _: Authenticated) -> actix_web::Result<HttpResponse> {
+    let list = open(&data, &name).await?;
+    let done = clear.done;
+    list.update(move |todos| {
+        todos.clear(done);
+        Some(())
+    }).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::FileStore;
+
+    #[test]
+    fn entries_are_added_edited_and_removed() {
+        let mut todos = Todos::default();
+        assert_eq!(todos.add("milk".to_string(), false).id, 0);
+        assert_eq!(todos.add("eggs".to_string(), true).id, 1);
+        let edited = todos.edit(0, Some("oat milk".to_string()), Some(true)).unwrap();
+        assert_eq!(edited, Entry { id: 0, done: true, content: "oat milk".to_string() });
+        assert_eq!(todos.edit(1, None, Some(false)).unwrap().content, "eggs");
+        assert_eq!(todos.edit(7, None, Some(true)), None);
+        assert_eq!(todos.remove(0).unwrap().content, "oat milk");
+        assert_eq!(todos.remove(0), None);
+        todos.add("bread".to_string(), true);
+        todos.clear(true);
+        assert_eq!(todos.entries.iter().map(|entry| entry.id).collect::<Vec<_>>(), [1]);
+        todos.clear(false);
+        assert!(todos.entries.is_empty());
+    }
+
+    #[test]
+    fn ids_are_never_reused() {
+        let mut todos = Todos::default();
+        todos.add("milk".to_string(), false);
+        let last = todos.add("eggs".to_string(), false);
+        todos.remove(last.id);
+        assert_eq!(todos.add("bread".to_string(), false).id, last.id + 1);
+        todos.clear(false);
+        assert_eq!(todos.add("tea".to_string(), false).id, last.id + 2);
+    }
+
+    #[actix_web::test]
+    async fn changes_survive_a_reload() {
+        let directory = tempfile::tempdir().unwrap();
+        let store: Arc<dyn ScoreStore> = Arc::new(FileStore::new(directory.path()).unwrap());
+        let list = Arc::new(TodoList::load("home", Arc::clone(&store)).unwrap());
+        list.update(|todos| Some(todos.add("milk".to_string(), false))).await.unwrap();
+        let eggs = list.update(|todos| Some(todos.add("eggs".to_string(), false))).await.unwrap().unwrap();
+        list.update(move |todos| todos.remove(eggs.id)).await.unwrap();
+
+        let reloaded = Arc::new(TodoList::load("home", store).unwrap());
+        assert_eq!(reloaded.entries(), list.entries());
+        let bread = reloaded.update(|todos| Some(todos.add("bread".to_string(), false))).await.unwrap().unwrap();
+        assert_eq!(bread.id, eggs.id + 1);
+    }
+
+    #[actix_web::test]
+    async fn failed_changes_are_not_kept() {
+        let directory = tempfile::tempdir().unwrap();
+        let store: Arc<dyn ScoreStore> = Arc::new(FileStore::new(directory.path()).unwrap());
+        let list = Arc::new(TodoList::load("home", store).unwrap());
+        list.update(|todos| Some(todos.add("milk".to_string(), false))).await.unwrap();
+        std::fs::remove_file(directory.path().join("todos/home.json")).unwrap();
+        std::fs::create_dir(directory.path().join("todos/home.json")).unwrap();
+        assert!(list.update(|todos| Some(todos.add("eggs".to_string(), false))).await.is_err());
+        assert_eq!(list.entries().iter().map(|entry| entry.content.as_str()).collect::<Vec<_>>(), ["milk"]);
+    }
+}
@@ -0,0 +1,323 @@
+use crate::protocol::{ArenaSnake, ServerMessage, VERSION};
+use serde::{Deserialize, Serialize};
+use snake_rules::Position;
+use std::collections::HashMap;
+
+// a full state at least this often so a decoder never drifts for long
+pub const KEYFRAME: i32 = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+impl Encoding {
+    // clients asking for anything we do not know keep getting JSON text frames
+    pub fn negotiate(requested: Option<&str>) -> Self {
+        match requested {
+            Some("msgpack") => Self::MessagePack,
+            _ => Self::Json,
+        }
+    }
+}
+
+// the next state of a snake: drop `dropped` parts from the tail, push `added` at the head, swap the listed food
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename = "state_delta")]
+pub struct Delta {
+    version: u64,
+    player: Option<String>,
+    tick: i32,
+    run: u32,
+    dropped: u32,
+    added: Vec<Position>,
+    eaten: Vec<[i32; 3]>,
+    spawned: Vec<[i32; 3]>,
+}
+
+// the next arena: forget the snakes that left, move the others like a state delta, swap the listed food
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename = "arena_delta")]
+pub struct ArenaDelta {
+    version: u64,
+    tick: i32,
+    left: Vec<u32>,
+    snakes: Vec<SnakeDelta>,
+    eaten: Vec<[i32; 3]>,
+    spawned: Vec<[i32; 3]>,
+}
+
+// a snake that joined since the last frame has nothing to drop and all of its parts added
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct SnakeDelta {
+    id: u32,
+    player: Option<String>,
+    run: u32,
+    dropped: u32,
+    added: Vec<Position>,
+}
+
+// how many parts to drop from the tail of `last` and which to push at its head to get `next`
+fn diff(last: &[Position], next: &[Position]) -> (u32, Vec<Position>) {
+    let dropped = (0..=last.len()).find(|&dropped| next.starts_with(&last[dropped..])).unwrap_or(last.len());
+    (dropped as u32, next[last.len() - dropped..].to_vec())
+}
+
+fn apply(parts: &mut Vec<Position>, dropped: u32, added: Vec<Position>) {
+    parts.drain(..(dropped as usize).min(parts.len()));
+    parts.extend(added);
+}
+
+// food that is gone, then food that is new
+fn swap(last: &[[i32; 3]], next: &[[i32; 3]]) -> (Vec<[i32; 3]>, Vec<[i32; 3]>) {
+    (
+        last.iter().filter(|position| !next.contains(position)).copied().collect(),
+        next.iter().filter(|position| !last.contains(position)).copied().collect(),
+    )
+}
+
+struct Snapshot {
+    tick: i32,
+    keyframe: i32,
+    snake: Vec<Position>,
+    food: Vec<[i32; 3]>,
+}
+
+struct ArenaSnapshot {
+    tick: i32,
+    keyframe: i32,
+    snakes: Vec<ArenaSnake>,
+    food: Vec<[i32; 3]>,
+}
+
+// MessagePack with field names, game states and arenas go as deltas against what this session was sent last
+#[derive(Default)]
+pub struct Encoder {
+    last: HashMap<Option<String>, Snapshot>,
+    arena: Option<ArenaSnapshot>,
+}
+
+// a delta only while ticks follow each other and the last keyframe is recent, and only if it is the smaller frame
+fn pick(delta: Option<(i32, Vec<u8>)>, tick: i32, full: Vec<u8>) -> (i32, Vec<u8>) {
+    match delta {
+        Some((keyframe, delta)) if delta.len() < full.len() => (keyframe, delta),
+        _ => (tick, full),
+    }
+}
+
+fn follows(tick: i32, last: i32, keyframe: i32) -> bool {
+    last + 1 == tick && tick - keyframe < KEYFRAME
+}
+
+impl Encoder {
+    pub fn encode(&mut self, message: &ServerMessage) -> Vec<u8> {
+        match message {
+            ServerMessage::State { player, tick, run, snake, food } => {
+                let full = message.encode_binary();
+                let delta = self.last.get(player)
+                    .filter(|last| follows(*tick, last.tick, last.keyframe))
+                    .map(|last| {
+                        let (dropped, added) = diff(&last.snake, snake);
+                        let (eaten, spawned) = swap(&last.food, food);
+                        let delta = Delta { version: VERSION, player: player.clone(), tick: *tick, run: *run, dropped, added, eaten, spawned };
+                        (last.keyframe, rmp_serde::to_vec_named(&delta).unwrap())
+                    });
+                // a short snake is cheaper to send whole than to describe
+                let (keyframe, bytes) = pick(delta, *tick, full);
+                self.last.insert(player.clone(), Snapshot { tick: *tick, keyframe, snake: snake.clone(), food: food.clone() });
+                bytes
+            }
+            ServerMessage::Arena { tick, snakes, food } => {
+                let full = message.encode_binary();
+                let delta = self.arena.as_ref()
+                    .filter(|last| follows(*tick, last.tick, last.keyframe))
+                    .map(|last| {
+                        let snakes = snakes.iter().map(|snake| {
+                            let before = last.snakes.iter().find(|before| before.id == snake.id).map_or(&[][..], |before| &before.parts);
+                            let (dropped, added) = diff(before, &snake.parts);
+                            SnakeDelta { id: snake.id, player: snake.player.clone(), run: snake.run, dropped, added }
+                        }).collect::<Vec<_>>();
+                        let left = last.snakes.iter().map(|before| before.id).filter(|id| !snakes.iter().any(|snake| snake.id == *id)).collect();
+                        let (eaten, spawned) = swap(&last.food, food);
+                        let delta = ArenaDelta { version: VERSION, tick: *tick, left, snakes, eaten, spawned };
+                        (last.keyframe, rmp_serde::to_vec_named(&delta).unwrap())
+                    });
+                let (keyframe, bytes) = pick(delta, *tick, full);
+                self.arena = Some(ArenaSnapshot { tick: *tick, keyframe, snakes: snakes.clone(), food: food.clone() });
+                bytes
+            }
+            _ => message.encode_binary(),
+        }
+    }
+}
+
+// what a client does with the frames above, Rust clients and tests can use it as is
+#[derive(Default)]
+pub struct Decoder {
+    last: HashMap<Option<String>, ServerMessage>,
+    arena: Option<ServerMessage>,
+}
+
+impl Decoder {
+    pub fn decode(&mut self, bytes: &[u8]) -> Result<ServerMessage, String> {
+        let value: serde_json::Value = rmp_serde::from_slice(bytes).map_err(|error| error.to_string())?;
+        match value.get("type").and_then(serde_json::Value::as_str) {
+            Some("state_delta") => self.state(serde_json::from_value(value).map_err(|error| error.to_string())?),
+            Some("arena_delta") => self.arena(serde_json::from_value(value).map_err(|error| error.to_string())?),
+            _ => {
+                let message: ServerMessage = serde_json::from_value(value).map_err(|error| error.to_string())?;
+                match &message {
+                    ServerMessage::State { player, .. } => { self.last.insert(player.clone(), message.clone()); }
+                    ServerMessage::Arena { .. } => self.arena = Some(message.clone()),
+                    _ => {}
+                }
+                Ok(message)
+            }
+        }
+    }
+
+    fn state(&mut self, Delta { player, tick, run, dropped, added, eaten, spawned, .. }: Delta) -> Result<ServerMessage, String> {
+        let Some(ServerMessage::State { tick: last_tick, run: last_run, snake, food, .. }) = self.last.get_mut(&player) else {
+            return Err("delta before any keyframe".to_string());
+        };
+        (*last_tick, *last_run) = (tick, run);
+        apply(snake, dropped, added);
+        food.retain(|position| !eaten.contains(position));
+        food.extend(spawned);
+        Ok(self.last[&player].clone())
+    }
+
+    fn arena(&mut self, ArenaDelta { tick, left, snakes: deltas, eaten, spawned, .. }: ArenaDelta) -> Result<ServerMessage, String> {
+        let Some(ServerMessage::Arena { tick: last_tick, snakes, food }) = &mut self.arena else {
+            return Err("delta before any keyframe".to_string());
+        };
+        *last_tick = tick;
+        snakes.retain(|snake| !left.contains(&snake.id));
+        for SnakeDelta { id, player, run, dropped, added } in deltas {
+            match snakes.iter_mut().find(|snake| snake.id == id) {
+                Some(snake) => {
+                    (snake.player, snake.run) = (player, run);
+                    apply(&mut snake.parts, dropped, added);
+                }
+                None => snakes.push(ArenaSnake { id, player, run, parts: added }),
+            }
+        }
+        // the server sends them by id
+        snakes.sort_by_key(|snake| snake.id);
+        food.retain(|position| !eaten.contains(position));
+        food.extend(spawned);
+        Ok(ServerMessage::Arena { tick, snakes: snakes.clone(), food: food.clone() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{self, ClientMessage, ErrorCode};
+
+    fn state(tick: i32, snake: &[[i32; 3]], food: &[[i32; 3]]) -> ServerMessage {
+        ServerMessage::State { player: Some("ada".to_string()), tick, run: 1, snake: snake.iter().copied().map(Position).collect(), food: food.to_vec() }
+    }
+
+    // a snake of `length` parts along the rows of the board, its head `tick` parts in
+    fn snake(tick: i32, length: i32) -> Vec<[i32; 3]> {
+        (tick..tick + length).map(|part| [part % 10, part / 10, 0]).collect()
+    }
+
+    fn messages() -> Vec<ServerMessage> {
+        vec![
+            ServerMessage::Welcome { session: 3, token: "token".to_string(), resumed: false, counter: -2 },
+            ServerMessage::Update { counter: 5, player: None },
+            state(1, &snake(0, 20), &[[5, 5, 0]]),
+            state(2, &snake(1, 20), &[[5, 5, 0]]),
+            state(3, &[snake(1, 20), snake(21, 1)].concat(), &[[7, 9, 0]]),
+            ServerMessage::error(Some(4), ErrorCode::NotPlaying, "start a game first"),
+            state(1, &snake(40, 20), &[]),
+            state(2, &snake(41, 20), &[[0, 9, 0]]),
+            state(3, &[[4, 4, 0]], &[]),
+        ]
+    }
+
+    #[test]
+    fn json_round_trip() {
+        for message in messages() {
+            assert_eq!(serde_json::from_str::<ServerMessage>(&message.encode()).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn message_pack_round_trip_with_deltas() {
+        let (mut encoder, mut decoder) = (Encoder::default(), Decoder::default());
+        let mut kinds = vec![];
+        for message in messages() {
+            let bytes = encoder.encode(&message);
+            kinds.push(rmp_serde::from_slice::<serde_json::Value>(&bytes).unwrap()["type"].as_str().unwrap().to_string());
+            assert_eq!(decoder.decode(&bytes).unwrap(), message);
+        }
+        // a restarted game starts over at tick 1 and a crashed snake is smaller whole
+        assert_eq!(kinds, ["welcome", "update", "state", "state_delta", "state_delta", "error", "state", "state_delta", "state"]);
+        let mut encoder = Encoder::default();
+        let keyframe = encoder.encode(&state(1, &snake(0, 40), &[[9, 9, 0]]));
+        assert!(encoder.encode(&state(2, &snake(1, 40), &[[9, 9, 0]])).len() * 2 < keyframe.len());
+    }
+
+    fn arena(tick: i32, snakes: &[(u32, Vec<[i32; 3]>)], food: &[[i32; 3]]) -> ServerMessage {
+        let snakes = snakes.iter().map(|(id, parts)| ArenaSnake {
+            id: *id,
+            player: (*id == 0).then(|| "ada".to_string()),
+            run: parts.len() as u32,
+            parts: parts.iter().copied().map(Position).collect(),
+        }).collect();
+        ServerMessage::Arena { tick, snakes, food: food.to_vec() }
+    }
+
+    #[test]
+    fn arenas_go_as_deltas_too() {
+        let frames = [
+            arena(1, &[(0, snake(0, 30))], &[[5, 5, 0]]),
+            // a second snake joins and the first eats
+            arena(2, &[(0, [snake(0, 30), snake(30, 1)].concat()), (1, snake(50, 30))], &[[6, 6, 0]]),
+            // the first crashes and respawns elsewhere
+            arena(3, &[(0, snake(90, 3)), (1, snake(51, 30))], &[[6, 6, 0]]),
+            // the first leaves
+            arena(4, &[(1, snake(52, 30))], &[]),
+            arena(6, &[(1, snake(54, 30))], &[]),
+        ];
+        let (mut encoder, mut decoder) = (Encoder::default(), Decoder::default());
+        let mut kinds = vec![];
+        for message in &frames {
+            let bytes = encoder.encode(message);
+            kinds.push(rmp_serde::from_slice::<serde_json::Value>(&bytes).unwrap()["type"].as_str().unwrap().to_string());
+            assert_eq!(&decoder.decode(&bytes).unwrap(), message);
+        }
+        // a skipped tick needs a keyframe
+        assert_eq!(kinds, ["arena", "arena_delta", "arena_delta", "arena_delta", "arena"]);
+        assert!(Decoder::default().decode(&encoder.encode(&arena(7, &[(1, snake(55, 30))], &[]))).is_err());
+    }
+
+    #[test]
+    fn keyframes_are_sent_periodically() {
+        let mut encoder = Encoder::default();
+        let mut decoder = Decoder::default();
+        let keyframes = (1..=2 * KEYFRAME + 1)
+            .filter(|&tick| {
+                let bytes = encoder.encode(&state(tick, &snake(tick, 30), &[]));
+                decoder.decode(&bytes).unwrap();
+                rmp_serde::from_slice::<serde_json::Value>(&bytes).unwrap()["type"] == "state"
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(keyframes, [1, 1 + KEYFRAME, 1 + 2 * KEYFRAME]);
+    }
+
+    #[test]
+    fn client_frames_decode_the_same_in_both_encodings() {
+        let json = r#"{"version":1,"seq":7,"type":"direction","direction":[0,-1,0]}"#;
+        let value: serde_json::Value = serde_json::from_str(json).unwrap();
+        let binary = rmp_serde::to_vec_named(&value).unwrap();
+        let (text, bytes) = (protocol::decode(json).unwrap(), protocol::decode_binary(&binary, Encoding::MessagePack).unwrap());
+        assert_eq!((text.seq, &text.message), (7, &ClientMessage::Direction { direction: [0, -1, 0] }));
+        assert_eq!((bytes.seq, &bytes.message), (text.seq, &text.message));
+    }
+}
@@ -0,0 +1,114 @@
+// Runs the whole server in process on an ephemeral port with its own data directory,
+// and talks to it the way the site does: plain HTTP requests and a real websocket.
+
+use actix_web::{dev::ServerHandle, rt};
+use futures_util::{SinkExt as _, StreamExt as _};
+use serde_json::{json, Value};
+use server::{config::Config, Counter};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tempfile::TempDir;
+use tokio::{io::{AsyncReadExt as _, AsyncWriteExt as _}, net::TcpStream};
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct TestServer {
+    pub address: SocketAddr,
+    pub directory: TempDir,
+    handle: ServerHandle,
+    data: Arc<Counter>,
+}
+
+impl TestServer {
+    pub fn start() -> Self {
+        Self::start_in(tempfile::tempdir().unwrap())
+    }
+
+    // for settings other than the defaults, the port and data directory are always the test's own
+    pub fn start_with(configure: impl FnOnce(&mut Config)) -> Self {
+        let directory = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        configure(&mut config);
+        Self::launch(Config { port: 0, data: directory.path().to_path_buf(), ..config }, directory)
+    }
+
+    // picks up whatever an earlier server left in the directory
+    pub fn start_in(directory: TempDir) -> Self {
+        let config = Config { port: 0, data: directory.path().to_path_buf(), ..Config::default() };
+        Self::launch(config, directory)
+    }
+
+    fn launch(config: Config, directory: TempDir) -> Self {
+        let started = server::start(config).unwrap();
+        let handle = started.server.handle();
+        rt::spawn(started.server);
+        Self { address: started.addresses[0], directory, handle, data: started.data }
+    }
+
+    // shuts down the way SIGTERM does and hands back the data directory
+    pub async fn stop(self) -> TempDir {
+        server::shutdown(self.handle, self.data).await;
+        self.directory
+    }
+
+    pub async fn request(&self, method: &str, path: &str, token: Option<&str>, body: Option<Value>) -> (u16, Value) {
+        let body = body.map(|body| body.to_string()).unwrap_or_default();
+        let authorization = token.map(|token| format!("Authorization: Bearer {token}\r\n")).unwrap_or_default();
+        let mut stream = TcpStream::connect(self.address).await.unwrap();
+        let request = format!(
+            "{method} {path} HTTP/1.1\r\nHost: {}\r\n{authorization}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            self.address,
+            body.len(),
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        rt::time::timeout(TIMEOUT, stream.read_to_string(&mut response)).await.unwrap().unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap_or_else(|_| Value::String(body.to_string())))
+    }
+
+    pub async fn register(&self, name: &str) -> String {
+        let (status, session) = self.request("POST", "/api/register", None, Some(json!({ "name": name, "password": "password" }))).await;
+        assert_eq!(status, 201, "{session}");
+        session["token"].as_str().unwrap().to_string()
+    }
+
+    // `path` includes any query, such as /echo?token=..., the welcome is left for the caller
+    pub async fn connect(&self, path: &str) -> Client {
+        let (socket, _) = tokio_tungstenite::connect_async(format!("ws://{}{path}", self.address)).await.unwrap();
+        Client { socket, seq: 0 }
+    }
+}
+
+pub struct Client {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    seq: u64,
+}
+
+impl Client {
+    pub async fn send(&mut self, kind: &str, fields: Value) -> u64 {
+        self.seq += 1;
+        let mut frame = json!({ "version": 1, "seq": self.seq, "type": kind });
+        frame.as_object_mut().unwrap().extend(fields.as_object().cloned().unwrap_or_default());
+        self.socket.send(Message::text(frame.to_string())).await.unwrap();
+        self.seq
+    }
+
+    pub async fn next(&mut self) -> Message {
+        loop {
+            let message = rt::time::timeout(TIMEOUT, self.socket.next()).await.expect("no message in time").unwrap().unwrap();
+            if !matches!(message, Message::Ping(_) | Message::Pong(_)) { return message; }
+        }
+    }
+
+    // skips everything else the server sends until a message of this type arrives
+    pub async fn expect(&mut self, kind: &str) -> Value {
+        loop {
+            let message = self.next().await;
+            let Ok(text) = message.to_text() else { continue };
+            let value: Value = serde_json::from_str(text).unwrap();
+            if value["type"] == kind { return value; }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use common::TestServer;
+use serde_json::json;
+use server::{protocol::ServerMessage, wire::Decoder};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_tungstenite::tungstenite::{protocol::frame::coding::CloseCode, Message};
+
+#[actix_web::test]
+async fn increments_are_acknowledged_and_broadcast() {
+    let server = TestServer::start();
+    let token = server.register("ada").await;
+    let mut player = server.connect(&format!("/echo?token={token}")).await;
+    let mut watcher = server.connect("/echo").await;
+    watcher.send("subscribe", json!({})).await;
+    assert_eq!(watcher.expect("ack").await["counter"], 0);
+
+    let seq = player.send("increment", json!({})).await;
+    let ack = player.expect("ack").await;
+    assert_eq!((ack["seq"].as_u64(), &ack["counter"]), (Some(seq), &json!(1)));
+    let update = watcher.expect("update").await;
+    assert_eq!((&update["counter"], &update["player"]), (&json!(1), &json!("ada")));
+
+    let (status, score) = server.request("POST", "/api/score/increment", Some(&token), None).await;
+    assert_eq!((status, &score["counter"]), (200, &json!(2)));
+    assert_eq!(watcher.expect("update").await["counter"], 2);
+}
+
+#[actix_web::test]
+async fn anonymous_sessions_cannot_change_anything() {
+    let server = TestServer::start();
+    let mut anonymous = server.connect("/echo").await;
+    anonymous.send("increment", json!({})).await;
+    assert_eq!(anonymous.expect("error").await["code"], "unauthorized");
+    let (status, _) = server.request("POST", "/api/score/increment", None, None).await;
+    assert_eq!(status, 401);
+    assert_eq!(server.request("GET", "/api/score", None, None).await.1["counter"], 0);
+}
+
+#[actix_web::test]
+async fn state_survives_a_restart() {
+    let server = TestServer::start();
+    let token = server.register("ada").await;
+    let mut player = server.connect(&format!("/ws/lab?token={token}")).await;
+    for _ in 0..3 {
+        player.send("increment", json!({})).await;
+        player.expect("ack").await;
+    }
+    let directory = server.stop().await;
+    assert_eq!(std::fs::read_to_string(directory.path().join("lab.txt")).unwrap(), "3");
+
+    let server = TestServer::start_in(directory);
+    assert_eq!(server.request("GET", "/api/score?room=lab", None, None).await.1["counter"], 3);
+    let points = server.request("GET", "/api/score/history?room=lab&bucket=86400", None, None).await.1["points"].clone();
+    assert_eq!(points.as_array().unwrap().iter().map(|point| point["changes"].as_u64().unwrap()).sum::<u64>(), 3);
+    let (status, score) = server.request("POST", "/api/score/increment?room=lab", Some(&token), None).await;
+    assert_eq!((status, &score["counter"]), (200, &json!(4)), "tokens stay valid because the signing key is kept with the data");
+}
+
+#[actix_web::test]
+async fn binary_sessions_rebuild_every_state() {
+    let server = TestServer::start();
+    let token = server.register("ada").await;
+    let (mut binary, mut decoder) = (server.connect(&format!("/echo?token={token}&encoding=msgpack")).await, Decoder::default());
+    assert!(matches!(decoder.decode(&binary.next().await.into_data()).unwrap(), ServerMessage::Welcome { .. }));
+    binary.send("play", json!({ "difficulty": 100 })).await;
+    let mut ticks = vec![];
+    while ticks.len() < 3 {
+        let message = binary.next().await;
+        assert!(message.is_binary(), "msgpack sessions only get binary frames");
+        if let ServerMessage::State { tick, snake, .. } = decoder.decode(&message.into_data()).unwrap() {
+            assert!(!snake.is_empty());
+            ticks.push(tick);
+        }
+    }
+    assert_eq!(ticks, [1, 2, 3]);
+}
+
+#[actix_web::test]
+async fn presence_counts_sessions_not_connections() {
+    let server = TestServer::start();
+    let mut watcher = server.connect("/ws/lobby").await;
+    watcher.send("subscribe", json!({})).await;
+    watcher.expect("ack").await;
+    let token = server.register("ada").await;
+
+    let mut player = server.connect(&format!("/ws/lobby?token={token}")).await;
+    let welcome = player.expect("welcome").await;
+    let joined = watcher.expect("player_joined").await;
+    assert_eq!((&joined["session"], &joined["player"], &joined["room"], &joined["total"]), (&welcome["session"], &json!("ada"), &json!(2), &json!(2)));
+    drop(player);
+    let left = watcher.expect("player_left").await;
+    assert_eq!((&left["session"], &left["room"], &left["total"]), (&welcome["session"], &json!(1), &json!(1)));
+
+    // a resumed session joins again as itself
+    let mut resumed = server.connect(&format!("/ws/lobby?token={token}&resume={}", welcome["token"].as_str().unwrap())).await;
+    let again = resumed.expect("welcome").await;
+    assert_eq!((&again["session"], &again["resumed"]), (&welcome["session"], &json!(true)));
+    assert_eq!(watcher.expect("player_joined").await["room"], 2);
+    watcher.send("presence", json!({})).await;
+    let presence = watcher.expect("presence").await;
+    assert_eq!((&presence["room"], &presence["total"]), (&json!(2), &json!(2)));
+    drop(resumed);
+    assert_eq!(watcher.expect("player_left").await["room"], 1);
+    watcher.send("presence", json!({})).await;
+    assert_eq!(watcher.expect("presence").await["room"], 1);
+}
+
+#[actix_web::test]
+async fn watched_todo_lists_stay_in_sync() {
+    let server = TestServer::start();
+    let token = server.register("ada").await;
+    let mut tab = server.connect("/echo").await;
+    let seq = tab.send("watch", json!({ "list": "home" })).await;
+    let snapshot = tab.expect("todos").await;
+    assert_eq!((snapshot["seq"].as_u64(), &snapshot["entries"]), (Some(seq), &json!([])));
+
+    let (status, entry) = server.request("POST", "/api/todos/home", Some(&token), Some(json!({ "content": "milk" }))).await;
+    assert_eq!(status, 201);
+    let pushed = tab.expect("todos").await;
+    assert_eq!((&pushed["list"], &pushed["entries"]), (&json!("home"), &json!([entry])));
+}
+
+const ADMIN: &str = "an admin token for the tests";
+
+#[actix_web::test]
+async fn the_admin_api_needs_the_configured_token() {
+    let disabled = TestServer::start();
+    assert_eq!(disabled.request("GET", "/api/admin/sessions", Some(ADMIN), None).await.0, 403);
+
+    let server = TestServer::start_with(|config| config.admin_token = Some(ADMIN.to_string()));
+    assert_eq!(server.request("GET", "/api/admin/sessions", None, None).await.0, 401);
+    assert_eq!(server.request("GET", "/api/admin/sessions", Some("not the admin token at all"), None).await.0, 401);
+    let player = server.register("ada").await;
+    assert_eq!(server.request("POST", "/api/admin/rooms/score/reset", Some(&player), None).await.0, 401);
+    assert_eq!(server.request("GET", "/api/admin/sessions", Some(ADMIN), None).await.0, 200);
+}
+
+#[actix_web::test]
+async fn admins_set_counters_list_and_kick_sessions() {
+    let server = TestServer::start_with(|config| config.admin_token = Some(ADMIN.to_string()));
+    let mut watcher = server.connect("/ws/lobby").await;
+    let session = watcher.expect("welcome").await["session"].clone();
+    watcher.send("subscribe", json!({})).await;
+    watcher.expect("ack").await;
+
+    let (status, body) = server.request("PUT", "/api/admin/rooms/lobby/counter", Some(ADMIN), Some(json!({ "counter": 42 }))).await;
+    assert_eq!((status, &body["counter"]), (200, &json!(42)));
+    let update = watcher.expect("update").await;
+    assert_eq!((&update["counter"], &update["player"]), (&json!(42), &json!(null)));
+
+    let (status, sessions) = server.request("GET", "/api/admin/sessions", Some(ADMIN), None).await;
+    assert_eq!(status, 200);
+    let listed = &sessions.as_array().unwrap()[..];
+    assert_eq!(listed.len(), 1);
+    assert_eq!((&listed[0]["id"], &listed[0]["room"], &listed[0]["peer"]), (&session, &json!("lobby"), &json!("127.0.0.1")));
+    let since = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    assert!(since.abs_diff(listed[0]["connected_at"].as_u64().unwrap()) < 60);
+
+    assert_eq!(server.request("DELETE", &format!("/api/admin/sessions/{session}"), Some(ADMIN), None).await.0, 204);
+    let Message::Close(Some(frame)) = watcher.next().await else { panic!("kicked sessions are closed") };
+    assert_eq!(frame.code, CloseCode::Policy);
+}
+
+#[actix_web::test]
+async fn silent_clients_are_evicted() {
+    let server = TestServer::start_with(|config| {
+        config.heartbeat = Duration::from_secs(1);
+        config.idle_timeout = Duration::from_secs(2);
+    });
+    let mut watcher = server.connect("/ws/lobby").await;
+    watcher.send("subscribe", json!({})).await;
+    watcher.expect("ack").await;
+
+    // never read, so the server's pings go unanswered
+    let _silent = server.connect("/ws/lobby").await;
+    assert_eq!(watcher.expect("player_joined").await["total"], 2);
+    let left = watcher.expect("player_left").await;
+    assert_eq!((&left["room"], &left["total"]), (&json!(1), &json!(1)));
+    watcher.send("presence", json!({})).await;
+    let presence = watcher.expect("presence").await;
+    assert_eq!((&presence["room"], &presence["total"]), (&json!(1), &json!(1)));
+}
@@ -9,9 +9,25 @@ import { ServerService } from './server.service';
     <div>
       <span [routerLink]="['/todo']" style='margin-right: 5px;'>ToDo</span>
       <span [routerLink]="['/snake']">Snake</span>
+      @if (server.online !== undefined) {
+        <span style='float: right;'>
+          {{ server.online }} {{ server.online === 1 ? 'player' : 'players' }} online
+          @if (server.joined) { ({{ server.joined }} just joined) }
+        </span>
+      }
     </div>
+    @if (server.announcement) {
+      <div class='announcement' (click)='server.announcement = undefined'>{{ server.announcement }}</div>
+    }
     <router-outlet />
   `,
+  styles: `
+    .announcement {
+      padding: 5px;
+      background-color: rgb(255, 230, 150);
+      cursor: pointer;
+    }
+  `,
 })
 export class AppComponent {
   title = 'site';
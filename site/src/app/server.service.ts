@@ -1,21 +1,230 @@
 import { Injectable } from '@angular/core';
 
+const VERSION = 1;
+// `ng serve` runs on its own port, a built site is served by the server itself
+const HOST = location.port === '4200' ? 'localhost:8080' : location.host;
+const SECURE = location.protocol === 'https:';
+
+export interface Player {
+  name: string,
+  total: number,
+  best: number,
+}
+
+export interface Entry {
+  id?: number,
+  done: boolean,
+  content: string,
+}
+
+export interface ChatLine {
+  player: string,
+  text: string,
+  at: number,
+}
+
+export interface GameState {
+  player: string | null,
+  tick: number,
+  run: number,
+  snake: number[][],
+  food: number[][],
+}
+
+interface ServerMessage extends Partial<GameState> {
+  version: number,
+  type: 'welcome' | 'ack' | 'update' | 'leaderboard' | 'state' | 'started' | 'todos' | 'presence' | 'player_joined' | 'player_left' | 'chat' | 'chat_history' | 'announcement' | 'error',
+  seq?: number,
+  session?: number,
+  token?: string,
+  room?: number,
+  counter?: number,
+  players?: Player[],
+  replay?: string,
+  list?: string,
+  entries?: Entry[],
+  text?: string,
+  at?: number,
+  lines?: ChatLine[],
+  code?: string,
+  message?: string,
+}
+
 @Injectable({
   providedIn: 'root'
 })
 export class ServerService {
-  socket: WebSocket = new WebSocket('ws://localhost:8080/echo');
+  socket!: WebSocket;
+  session: number | undefined;
   score: number | undefined;
+  leaderboard: Player[] = [];
+  name: string | null = localStorage.getItem('name');
+  game: GameState | undefined;
+  replay: string | undefined;
+  todos: Entry[] = [];
+  online: number | undefined;
+  joined: string | undefined;
+  chat: ChatLine[] = [];
+  announcement: string | undefined;
+  watching: string | undefined;
+  seq: number = 0;
+  ready!: Promise<unknown>;
 
   constructor() {
+    this.connect();
+  }
+
+  // reconnecting resumes the session, the token decides who the session plays as
+  connect() {
     let listener = async (event: MessageEvent<any>) => {
-      this.score = Number(event.data);
-      //this.socket.removeEventListener('message', listener);
+      let message: ServerMessage = JSON.parse(event.data);
+      switch (message.type) {
+        case 'welcome': {
+          this.session = message.session;
+          sessionStorage.setItem('token', message.token!);
+          this.score = message.counter;
+          break;
+        }
+        case 'ack':
+        case 'update': {
+          this.score = message.counter;
+          break;
+        }
+        case 'leaderboard': {
+          this.leaderboard = message.players!;
+          break;
+        }
+        case 'state': {
+          if (message.player === this.name) {
+            this.game = message as GameState;
+          }
+          break;
+        }
+        case 'started': {
+          this.replay = message.replay;
+          break;
+        }
+        case 'todos': {
+          this.todos = message.entries!;
+          break;
+        }
+        case 'presence':
+        case 'player_left': {
+          this.online = message.room;
+          break;
+        }
+        case 'chat': {
+          this.chat = [...this.chat, message as ChatLine].slice(-50);
+          break;
+        }
+        case 'announcement': {
+          this.announcement = message.text;
+          break;
+        }
+        case 'chat_history': {
+          this.chat = message.lines!;
+          break;
+        }
+        case 'player_joined': {
+          this.online = message.room;
+          this.joined = message.player ?? 'someone';
+          break;
+        }
+        case 'error': {
+          if (message.code === 'unauthorized') {
+            this.logout();
+          }
+          console.error(`server error ${message.code}: ${message.message}`);
+          break;
+        }
+      }
     };
+    this.socket?.close();
+    this.socket = new WebSocket(ServerService.url());
     this.socket.addEventListener('message', listener);
+    // the server refuses the upgrade outright when the stored token no longer verifies
+    this.socket.addEventListener('error', () => {
+      if (this.socket.readyState === WebSocket.CLOSED && localStorage.getItem('auth')) {
+        this.logout();
+        this.connect();
+      }
+    });
+    this.socket.addEventListener('open', () => {
+      this.send('subscribe');
+      this.send('presence');
+      if (this.watching) {
+        this.send('watch', { list: this.watching });
+      }
+    });
+    this.ready = new Promise(resolve => this.socket.addEventListener('open', resolve));
+  }
+
+  static url(): string {
+    let params = new URLSearchParams();
+    let resume = sessionStorage.getItem('token');
+    let token = localStorage.getItem('auth');
+    if (resume) { params.set('resume', resume); }
+    if (token) { params.set('token', token); }
+    return `${SECURE ? 'wss' : 'ws'}://${HOST}/echo` + (params.size ? `?${params}` : '');
+  }
+
+  static headers(): Record<string, string> {
+    let token = localStorage.getItem('auth');
+    return { 'content-type': 'application/json', ...(token ? { authorization: `Bearer ${token}` } : {}) };
+  }
+
+  async login(name: string, password: string, create: boolean): Promise<string | undefined> {
+    let response = await fetch(`${SECURE ? 'https' : 'http'}://${HOST}/api/${create ? 'register' : 'login'}`, {
+      method: 'POST',
+      headers: ServerService.headers(),
+      body: JSON.stringify({ name, password }),
+    });
+    if (!response.ok) {
+      return await response.text();
+    }
+    let session: { name: string, token: string } = await response.json();
+    localStorage.setItem('name', session.name);
+    localStorage.setItem('auth', session.token);
+    this.name = session.name;
+    this.connect();
+    return undefined;
+  }
+
+  // an expired or revoked token leaves the session read-only
+  logout() {
+    localStorage.removeItem('name');
+    localStorage.removeItem('auth');
+    this.name = null;
+  }
+
+  // changes go over REST, the server then pushes the whole list to every watcher
+  todo(method: string, path: string, body?: object) {
+    fetch(`${SECURE ? 'https' : 'http'}://${HOST}/api/todos/${path}`, {
+      method,
+      headers: ServerService.headers(),
+      body: body && JSON.stringify(body),
+    }).catch(error => console.error(`todo request failed: ${error}`));
+  }
+
+  send(type: string, fields: object = {}) {
+    this.seq += 1;
+    this.socket.send(JSON.stringify({ version: VERSION, seq: this.seq, type, ...fields }));
+  }
+
+  watch(list: string) {
+    this.watching = list;
+    this.send('watch', { list });
+  }
+
+  play(difficulty: number) {
+    this.send('play', { difficulty });
+  }
+
+  say(text: string) {
+    this.send('chat', { text });
   }
 
-  incrementScore() {
-    this.socket.send("");
+  steer(direction: number[]) {
+    this.send('direction', { direction });
   }
 }
@@ -2,11 +2,6 @@ import { Component, HostListener, inject } from '@angular/core';
 import { FormsModule } from '@angular/forms';
 import { ServerService } from '../server.service';
 
-interface Food {
-  position: number[],
-  time: number,
-}
-
 @Component({
   selector: 'app-snake',
   imports: [FormsModule],
@@ -23,11 +18,34 @@ interface Food {
         type='range'
         min='1' max='100'
         [(ngModel)]='difficulty'
+        (change)='server.play(difficulty)'
       />
     </div>
+    @if (server.name) {
+      <div>Playing as {{ server.name }} <button (click)='logout()'>Log out</button></div>
+    } @else {
+      <div>
+        Name <input [(ngModel)]='name'/>
+        Password <input type='password' [(ngModel)]='password'/>
+        <button (click)='login(false)'>Log in</button>
+        <button (click)='login(true)'>Register</button>
+      </div>
+      @if (failure) { <div>{{ failure }}</div> }
+    }
     @if (server.score !== undefined) {
       <div>Score: {{ server.score }}</div>
     }
+    @for (player of server.leaderboard; track player.name) {
+      <div>{{ $index + 1 }}. {{ player.name }} {{ player.best }} ({{ player.total }})</div>
+    }
+    <div class='chat'>
+      @for (line of server.chat; track $index) {
+        <div><b>{{ line.player }}</b> {{ line.text }}</div>
+      }
+      @if (server.name) {
+        <input maxlength='500' [(ngModel)]='message' (keyup.enter)='say()' placeholder='Say something'/>
+      }
+    </div>
   `,
   styles: `
     .board {
@@ -40,6 +58,11 @@ interface Food {
       height: 30px;
     }
 
+    .chat {
+      max-height: 200px;
+      overflow-y: auto;
+    }
+
     .snake  { background-color: red; }
     .food   { background-color: green; }
     .empty  { background-color: rgb(64, 128, 255); }
@@ -47,79 +70,50 @@ interface Food {
 })
 export class SnakeComponent {
   server: ServerService = inject(ServerService);
-  snake: number[][] = [[0, 0], [0, 1]];
-  direction: number[] = [0, 1];
-  food: Food[] = [];
   difficulty: number = 50;
+  name: string = '';
+  password: string = '';
+  failure: string | undefined;
+  message: string = '';
 
   async ngOnInit() {
-    let iteration = 0;
-    while (true) {
-      this.forward();
-      if (iteration % 5 === 0) {
-        this.food.push({
-          position: this.unoccupiedPosition(),
-          time: iteration,
-        });
-      }
-      for (let i = 0; i < this.food.length; i++) {
-        if (iteration - this.food[this.food.length - i - 1].time >= 25) {
-          this.food.splice(this.food.length - i - 1, 1);
-        }
-      }
-      iteration += 1;
-      await new Promise(resolve => setTimeout(resolve, 400 - this.difficulty * 3));
-    }
+    await this.server.ready;
+    this.server.play(this.difficulty);
   }
 
   @HostListener('window:keyup', ['$event'])
   keyEvent(event: KeyboardEvent) {
+    if (event.target instanceof HTMLInputElement) {
+      return;
+    }
     switch (event.key) {
-      case 'w': { this.setDirection([0, -1]); break; }
-      case 's': { this.setDirection([0, 1]);  break; }
-      case 'd': { this.setDirection([1, 0]);  break; }
-      case 'a': { this.setDirection([-1, 0]); break; }
+      case 'w': { this.server.steer([0, -1, 0]); break; }
+      case 's': { this.server.steer([0, 1, 0]);  break; }
+      case 'd': { this.server.steer([1, 0, 0]);  break; }
+      case 'a': { this.server.steer([-1, 0, 0]); break; }
     }
   }
 
-  forward() {
-    let newPosition = [
-      this.modulo((this.snake[this.snake.length - 1][0] + this.direction[0]), 10),
-      this.modulo((this.snake[this.snake.length - 1][1] + this.direction[1]), 10),
-    ];
-    let element = this.snake.shift()!;
-    switch (this.gridPosition(newPosition[0], newPosition[1])) {
-      case 'food': {
-        for (let i = 0; i < this.food.length; i++) {
-          if (this.food[i].position[0] === newPosition[0] && this.food[i].position[1] === newPosition[1]) {
-            this.food.splice(i, 1);
-            break;
-          }
-        }
-        this.server.incrementScore();
-        this.snake.unshift(element);
-        if (element[0] === newPosition[0] && element[1] === newPosition[1]) {
-          this.die();
-        }
-        break;
-      }
-      case 'snake': {
-        this.die();
-        break;
-
-      }
+  async login(create: boolean) {
+    this.failure = await this.server.login(this.name, this.password, create);
+    if (this.failure === undefined) {
+      this.password = '';
+      await this.server.ready;
+      this.server.play(this.difficulty);
     }
-    this.snake.push(newPosition);
   }
 
-  die() {
-    this.snake = [this.snake[this.snake.length - 1]];
+  async logout() {
+    this.server.logout();
+    this.server.connect();
+    await this.server.ready;
+    this.server.play(this.difficulty);
   }
 
-  unoccupiedPosition(): number[] {
-    while (true) {
-      let position = [Math.floor(Math.random() * 10), Math.floor(Math.random() * 10)];
-      if (this.gridPosition(position[0], position[1]) === 'empty') return position;
+  say() {
+    if (this.message.trim()) {
+      this.server.say(this.message);
+      this.message = '';
     }
   }
 
@@ -134,31 +128,16 @@ export class SnakeComponent {
   }
 
   gridPosition(x: number, y: number): string {
-      for (let part of this.snake) {
+      for (let part of this.server.game?.snake ?? []) {
         if (part[0] === x && part[1] === y) {
           return 'snake';
         }
       }
-      for (let food of this.food) {
-        if (food.position[0] === x && food.position[1] === y) {
+      for (let food of this.server.game?.food ?? []) {
+        if (food[0] === x && food[1] === y) {
           return 'food';
         }
       }
       return 'empty';
   }
-
-  setDirection(direction: number[]) {
-    let illegalDirection = [
-      -(this.snake[this.snake.length - 1][0] - this.snake[this.snake.length - 2][0]),
-      -(this.snake[this.snake.length - 1][1] - this.snake[this.snake.length - 2][1]),
-    ];
-    if (direction[0] === illegalDirection[0] && direction[1] === illegalDirection[1]) {
-      return;
-    }
-    this.direction = direction;
-  }
-
-  modulo(value: number, x: number): number {
-    return ((value % x) + x) % x;
-  }
 }
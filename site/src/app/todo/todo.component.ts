@@ -1,10 +1,8 @@
-import { Component, EventEmitter, Input, Output } from '@angular/core';
+import { Component, EventEmitter, Input, Output, inject } from '@angular/core';
 import { FormsModule } from '@angular/forms';
+import { Entry, ServerService } from '../server.service';
 
-interface Entry {
-  done: boolean,
-  content: string,
-}
+const LIST = 'default';
 
 @Component({
   selector: 'todo-entry',
@@ -80,35 +78,62 @@ export class TodoEntry {
   styles: ``,
 })
 export class TodoComponent {
-  entries: Entry[] = [];
+  server: ServerService = inject(ServerService);
   newEntry: string = '';
 
+  constructor() {
+    this.server.ready.then(() => this.server.watch(LIST));
+  }
+
+  get entries(): Entry[] {
+    return this.server.todos;
+  }
+
+  set entries(entries: Entry[]) {
+    this.server.todos = entries;
+  }
+
+  // entries change locally right away, the list the server pushes back replaces them
   addEntry(content: string) {
     this.entries.push({
       done: false,
       content,
     });
     this.newEntry = '';
+    this.server.todo('POST', LIST, { content });
   }
 
   toggleEntryDone(id: number) {
     this.entries[id].done = !this.entries[id].done;
+    this.update(this.entries[id], { done: this.entries[id].done });
   }
 
   editEntry(id: number, content: string) {
     this.entries[id].content = content;
+    this.update(this.entries[id], { content });
   }
 
   deleteEntry(id: number) {
-    this.entries.splice(id, 1);
+    let [entry] = this.entries.splice(id, 1);
+    if (entry.id !== undefined) {
+      this.server.todo('DELETE', `${LIST}/${entry.id}`);
+    }
   }
 
   clean() {
     this.entries = this.entries.filter(entry => ! entry.done);
+    this.server.todo('DELETE', `${LIST}?done=true`);
   }
 
   clear() {
     this.entries = [];
+    this.server.todo('DELETE', LIST);
+  }
+
+  update(entry: Entry, fields: Partial<Entry>) {
+    if (entry.id !== undefined) {
+      this.server.todo('PATCH', `${LIST}/${entry.id}`, fields);
+    }
   }
 
   countDoneEntries(): number {
@@ -0,0 +1,450 @@
+use rand::{Rng as _, SeedableRng as _};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+
+mod replay;
+
+pub use replay::{Change, Diverged, Event, Playback, Replay};
+
+// random draws before falling back to listing the free cells, which only a nearly full board needs
+const ATTEMPTS: usize = 1000;
+
+fn free_cells(size: [i32; 3], occupied: &HashSet<[i32; 3]>) -> Vec<[i32; 3]> {
+    (0..size[0])
+        .flat_map(|x| (0..size[1]).flat_map(move |y| (0..size[2]).map(move |z| [x, y, z])))
+        .filter(|position| !occupied.contains(position))
+        .collect()
+}
+
+#[derive(Clone)]
+pub struct Game {
+    pub size: [i32; 3],
+    pub food: Vec<Food>,
+    pub snake: Snake,
+    pub food_lifetime: i32,
+    pub food_interval: i32,
+    // food placement only draws from here, so a seed and the same inputs give the same game
+    pub rng: ChaCha8Rng,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Step {
+    Moved,
+    Ate,
+    Crashed,
+}
+
+impl Game {
+    pub fn new(size: [i32; 3]) -> Self {
+        Self::seeded(size, rand::random())
+    }
+
+    pub fn seeded(size: [i32; 3], seed: u64) -> Self {
+        Self {
+            size,
+            food: vec![],
+            snake: Snake::new(),
+            food_lifetime: 25000,
+            food_interval: 1,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn run(&mut self, iteration: i32) -> Step {
+        let step = self.forward();
+        for i in (0..self.food.len()).rev() {
+            if iteration - self.food[i].time > self.food_lifetime {
+                self.food.remove(i);
+            }
+        }
+        if iteration % self.food_interval == 0 {
+            if let Some(position) = self.unoccupied() {
+                self.food.push(Food {
+                    position,
+                    time: iteration,
+                });
+            }
+        }
+        step
+    }
+
+    pub fn forward(&mut self) -> Step {
+        let new_position = (self.snake.parts[self.snake.parts.len() - 1] + self.snake.direction) % self.size;
+        let mut step = Step::Moved;
+        if self.snake.parts.iter().skip(1).any(|element| element.0 == new_position.0) {
+            self.snake.parts.drain(..self.snake.parts.len() - 2);
+            step = Step::Crashed;
+        } else if let Some(i) = self.food.iter().position(|element| element.position == new_position.0) {
+            self.food.remove(i);
+            step = Step::Ate;
+            if self.snake.parts[0].0 == new_position.0 {
+                self.snake.parts.drain(..self.snake.parts.len() - 2);
+                step = Step::Crashed;
+            }
+        } else {
+            self.snake.parts.pop_front();
+        }
+        self.snake.parts.push_back(new_position);
+        step
+    }
+
+    // None once the snake and the food cover the whole board
+    pub fn unoccupied(&mut self) -> Option<[i32; 3]> {
+        for _ in 0..ATTEMPTS {
+            let position = [
+                self.rng.random_range(0..self.size[0]),
+                self.rng.random_range(0..self.size[1]),
+                self.rng.random_range(0..self.size[2]),
+            ];
+            if self.food.iter().any(|element| element.position == position) { continue; }
+            if self.snake.parts.contains(&Position(position)) { continue; }
+            return Some(position);
+        }
+        let occupied = self.food.iter().map(|element| element.position).chain(self.snake.parts.iter().map(|part| part.0)).collect();
+        let free = free_cells(self.size, &occupied);
+        (!free.is_empty()).then(|| free[self.rng.random_range(0..free.len())])
+    }
+}
+
+#[derive(Clone)]
+pub struct Arena {
+    pub size: [i32; 3],
+    pub food: Vec<Food>,
+    pub snakes: BTreeMap<u32, Snake>,
+    pub food_lifetime: i32,
+    pub food_interval: i32,
+    next_id: u32,
+    // joined or crashed snakes that found no room to spawn yet
+    waiting: BTreeSet<u32>,
+}
+
+impl Arena {
+    pub fn new(size: [i32; 3]) -> Self {
+        Self {
+            size,
+            food: vec![],
+            snakes: BTreeMap::new(),
+            food_lifetime: 25000,
+            food_interval: 1,
+            next_id: 0,
+            waiting: BTreeSet::new(),
+        }
+    }
+
+    pub fn join(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        match self.spawn() {
+            Some(snake) => { self.snakes.insert(id, snake); }
+            None => { self.waiting.insert(id); }
+        }
+        id
+    }
+
+    pub fn leave(&mut self, id: u32) {
+        self.snakes.remove(&id);
+        self.waiting.remove(&id);
+    }
+
+    pub fn run(&mut self, iteration: i32) -> Vec<(u32, Step)> {
+        let steps = self.forward();
+        for i in (0..self.food.len()).rev() {
+            if iteration - self.food[i].time > self.food_lifetime {
+                self.food.remove(i);
+            }
+        }
+        if iteration % self.food_interval == 0 {
+            if let Some(position) = self.unoccupied() {
+                self.food.push(Food {
+                    position,
+                    time: iteration,
+                });
+            }
+        }
+        steps
+    }
+
+    // moves every snake at once, then crashes heads that meet another head or any body, crashed snakes respawn once there is room
+    pub fn forward(&mut self) -> Vec<(u32, Step)> {
+        let mut steps = vec![];
+        for (id, snake) in &mut self.snakes {
+            let new_position = (snake.parts[snake.parts.len() - 1] + snake.direction) % self.size;
+            if let Some(i) = self.food.iter().position(|element| element.position == new_position.0) {
+                self.food.remove(i);
+                steps.push((*id, Step::Ate));
+            } else {
+                snake.parts.pop_front();
+                steps.push((*id, Step::Moved));
+            }
+            snake.parts.push_back(new_position);
+        }
+        let heads: Vec<_> = self.snakes.iter().map(|(id, snake)| (*id, snake.parts[snake.parts.len() - 1])).collect();
+        for (id, step) in &mut steps {
+            let head = self.snakes[id].parts[self.snakes[id].parts.len() - 1];
+            let head_to_head = heads.iter().any(|(other, position)| other != id && *position == head);
+            let head_to_body = self.snakes.values().any(|snake| snake.parts.iter().rev().skip(1).any(|part| *part == head));
+            if head_to_head || head_to_body { *step = Step::Crashed; }
+        }
+        for (id, step) in &steps {
+            if *step == Step::Crashed {
+                self.snakes.remove(id);
+                self.waiting.insert(*id);
+            }
+        }
+        for id in std::mem::take(&mut self.waiting) {
+            match self.spawn() {
+                Some(snake) => { self.snakes.insert(id, snake); }
+                None => { self.waiting.insert(id); }
+            }
+        }
+        steps
+    }
+
+    fn occupied(&self, position: [i32; 3]) -> bool {
+        self.food.iter().any(|element| element.position == position)
+            || self.snakes.values().any(|snake| snake.parts.contains(&Position(position)))
+    }
+
+    fn occupied_cells(&self) -> HashSet<[i32; 3]> {
+        self.food.iter().map(|element| element.position)
+            .chain(self.snakes.values().flat_map(|snake| snake.parts.iter().map(|part| part.0)))
+            .collect()
+    }
+
+    fn random_cell(&self) -> [i32; 3] {
+        let mut rng = rand::rng();
+        [
+            rng.random_range(0..self.size[0]),
+            rng.random_range(0..self.size[1]),
+            rng.random_range(0..self.size[2]),
+        ]
+    }
+
+    // None once the snakes and the food cover the whole board
+    pub fn unoccupied(&self) -> Option<[i32; 3]> {
+        (0..ATTEMPTS).map(|_| self.random_cell()).find(|position| !self.occupied(*position)).or_else(|| {
+            let free = free_cells(self.size, &self.occupied_cells());
+            (!free.is_empty()).then(|| free[rand::rng().random_range(0..free.len())])
+        })
+    }
+
+    // None when no three free cells in a row are left
+    fn spawn(&self) -> Option<Snake> {
+        let fits = |tail: [i32; 3]| {
+            let parts: VecDeque<_> = (0..3).map(|i| (Position(tail) + Position([i, 0, 0])) % self.size).collect();
+            (!parts.iter().any(|part| self.occupied(part.0))).then_some(parts)
+        };
+        let parts = (0..ATTEMPTS).find_map(|_| fits(self.random_cell()))
+            .or_else(|| free_cells(self.size, &self.occupied_cells()).into_iter().find_map(fits))?;
+        Some(Snake {
+            parts,
+            direction: Position([1, 0, 0]),
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Food {
+    pub time: i32,
+    pub position: [i32; 3],
+}
+
+#[derive(Clone, Debug)]
+pub struct Snake {
+    pub parts: VecDeque<Position>,
+    pub direction: Direction,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct Position(pub [i32; 3]);
+pub type Direction = Position;
+
+pub const DIRECTIONS: [Direction; 6] = [
+    Position([1, 0, 0]),
+    Position([-1, 0, 0]),
+    Position([0, 1, 0]),
+    Position([0, -1, 0]),
+    Position([0, 0, 1]),
+    Position([0, 0, -1]),
+];
+
+impl std::ops::Add for Position {
+    type Output = Position;
+
+    fn add(self, other: Self) -> Self::Output {
+        Self([
+             self.0[0] + other.0[0],
+             self.0[1] + other.0[1],
+             self.0[2] + other.0[2],
+        ])
+    }
+}
+
+impl std::ops::Neg for Position {
+    type Output = Position;
+
+    fn neg(self) -> Self::Output {
+        Self([
+             -self.0[0],
+             -self.0[1],
+             -self.0[2],
+        ])
+    }
+}
+
+impl std::ops::Index<usize> for Position {
+    type Output = i32;
+
+    fn index(&self, position: usize) -> &Self::Output {
+        &self.0[position]
+    }
+}
+
+impl std::ops::Rem<[i32; 3]> for Position {
+    type Output = Position;
+
+    fn rem(self, value: [i32; 3]) -> Self::Output {
+        Self([
+            self.0[0].rem_euclid(value[0]),
+            self.0[1].rem_euclid(value[1]),
+            self.0[2].rem_euclid(value[2]),
+        ])
+    }
+}
+
+impl Default for Snake {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Snake {
+    pub fn new() -> Self {
+        Self {
+            parts: VecDeque::from_iter([
+                Position([0, 0, 0]),
+                Position([1, 0, 0]),
+                Position([2, 0, 0]),
+            ]),
+            direction: Position([1, 0, 0]),
+        }
+    }
+
+    pub fn set_direction(&mut self, new_direction: Direction) {
+        let delta = self.parts[self.parts.len() - 1] + -self.parts[self.parts.len() - 2];
+        if new_direction == -delta { return; }
+        self.direction = new_direction;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snake(parts: &[[i32; 3]], direction: [i32; 3]) -> Snake {
+        Snake { parts: parts.iter().copied().map(Position).collect(), direction: Position(direction) }
+    }
+
+    #[test]
+    fn a_full_board_spawns_no_food() {
+        let mut game = Game::seeded([2, 2, 1], 1);
+        game.snake = snake(&[[0, 0, 0], [1, 0, 0], [1, 1, 0]], [-1, 0, 0]);
+        game.food.push(Food { time: 0, position: [0, 1, 0] });
+        assert_eq!(game.run(1), Step::Ate);
+        assert_eq!(game.snake.parts.len(), 4);
+        assert_eq!(game.unoccupied(), None);
+        assert!(game.food.is_empty());
+    }
+
+    #[test]
+    fn the_last_free_cell_is_found() {
+        let mut game = Game::seeded([10, 10, 1], 1);
+        let parts: Vec<_> = (0..10).flat_map(|y| (0..10).map(move |x| [x, y, 0])).filter(|cell| *cell != [4, 7, 0]).collect();
+        game.snake = snake(&parts, [1, 0, 0]);
+        assert_eq!(game.unoccupied(), Some([4, 7, 0]));
+    }
+
+    #[test]
+    fn snakes_wait_for_room_to_spawn() {
+        let mut arena = Arena::new([3, 1, 1]);
+        let first = arena.join();
+        let second = arena.join();
+        assert_eq!(arena.snakes.keys().copied().collect::<Vec<_>>(), [first]);
+        assert_eq!(arena.unoccupied(), None);
+        arena.run(1);
+        assert!(arena.food.is_empty());
+        arena.leave(first);
+        arena.run(2);
+        assert_eq!(arena.snakes.keys().copied().collect::<Vec<_>>(), [second]);
+    }
+
+    #[test]
+    fn eating_grows_the_snake() {
+        let mut game = Game::seeded([10, 10, 1], 1);
+        game.food.push(Food { time: 0, position: [3, 0, 0] });
+        assert_eq!(game.forward(), Step::Ate);
+        assert_eq!(game.snake.parts.len(), 4);
+        assert!(game.food.is_empty());
+        assert_eq!(game.forward(), Step::Moved);
+        assert_eq!(game.snake.parts.len(), 4);
+    }
+
+    #[test]
+    fn snakes_wrap_around_the_edges() {
+        let mut game = Game::seeded([10, 10, 1], 1);
+        game.snake = snake(&[[7, 0, 0], [8, 0, 0], [9, 0, 0]], [1, 0, 0]);
+        assert_eq!(game.forward(), Step::Moved);
+        assert_eq!(game.snake.parts.back(), Some(&Position([0, 0, 0])));
+    }
+
+    #[test]
+    fn biting_the_body_crashes_but_the_tail_moves_away() {
+        let mut game = Game::seeded([10, 10, 1], 1);
+        game.snake = snake(&[[0, 0, 0], [1, 0, 0], [1, 1, 0], [0, 1, 0]], [0, -1, 0]);
+        assert_eq!(game.forward(), Step::Moved);
+        game.snake = snake(&[[0, 0, 0], [1, 0, 0], [2, 0, 0], [2, 1, 0], [1, 1, 0]], [0, -1, 0]);
+        assert_eq!(game.forward(), Step::Crashed);
+        assert_eq!(game.snake.parts, snake(&[[2, 1, 0], [1, 1, 0], [1, 0, 0]], [0, -1, 0]).parts);
+    }
+
+    #[test]
+    fn heads_that_meet_both_crash_and_respawn() {
+        let mut arena = Arena::new([10, 10, 1]);
+        arena.snakes.insert(0, snake(&[[0, 0, 0], [1, 0, 0], [2, 0, 0]], [1, 0, 0]));
+        arena.snakes.insert(1, snake(&[[6, 0, 0], [5, 0, 0], [4, 0, 0]], [-1, 0, 0]));
+        assert_eq!(arena.forward(), [(0, Step::Crashed), (1, Step::Crashed)]);
+        assert_eq!(arena.snakes.len(), 2);
+        assert!(arena.snakes.values().all(|snake| snake.parts.len() == 3));
+    }
+
+    #[test]
+    fn running_into_another_body_only_crashes_the_runner() {
+        let mut arena = Arena::new([10, 10, 1]);
+        arena.snakes.insert(0, snake(&[[0, 2, 0], [1, 2, 0], [2, 2, 0]], [1, 0, 0]));
+        arena.snakes.insert(1, snake(&[[3, 0, 0], [3, 1, 0], [3, 2, 0]], [0, 1, 0]));
+        assert_eq!(arena.forward(), [(0, Step::Crashed), (1, Step::Moved)]);
+        let respawned = &arena.snakes[&0];
+        assert_eq!(respawned.parts.len(), 3);
+        assert!(respawned.parts.iter().all(|part| !arena.snakes[&1].parts.contains(part)));
+        assert_eq!(arena.snakes[&1].parts, snake(&[[3, 1, 0], [3, 2, 0], [3, 3, 0]], [0, 1, 0]).parts);
+    }
+
+    #[test]
+    fn food_is_shared_and_eaten_once() {
+        let mut arena = Arena::new([10, 10, 1]);
+        arena.food.push(Food { time: 0, position: [3, 5, 0] });
+        arena.snakes.insert(0, snake(&[[0, 5, 0], [1, 5, 0], [2, 5, 0]], [1, 0, 0]));
+        arena.snakes.insert(1, snake(&[[6, 0, 0], [7, 0, 0], [8, 0, 0]], [1, 0, 0]));
+        assert_eq!(arena.forward(), [(0, Step::Ate), (1, Step::Moved)]);
+        assert_eq!(arena.snakes[&0].parts.len(), 4);
+        assert!(arena.food.is_empty());
+
+        // whoever reaches it first eats it, but heads on the same cell still crash
+        arena.food.push(Food { time: 0, position: [5, 7, 0] });
+        arena.snakes.insert(0, snake(&[[2, 7, 0], [3, 7, 0], [4, 7, 0]], [1, 0, 0]));
+        arena.snakes.insert(1, snake(&[[5, 9, 0], [5, 8, 0]], [0, -1, 0]));
+        assert_eq!(arena.forward(), [(0, Step::Crashed), (1, Step::Crashed)]);
+        assert!(arena.food.is_empty());
+    }
+}
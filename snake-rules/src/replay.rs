@@ -0,0 +1,151 @@
+use crate::{Direction, Game, Step};
+use serde::{Deserialize, Serialize};
+
+// everything needed to play a game again: its settings, its seed and what the player did on which tick
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Replay {
+    pub size: [i32; 3],
+    pub seed: u64,
+    pub food_lifetime: i32,
+    pub food_interval: i32,
+    pub ticks: i32,
+    pub events: Vec<Event>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Event {
+    pub tick: i32,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    // applied right before the tick runs
+    Direction(Direction),
+    // spawned while the tick ran, kept to catch replays that no longer match the rules
+    Food([i32; 3]),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Diverged {
+    pub tick: i32,
+}
+
+impl Replay {
+    pub fn new(size: [i32; 3], seed: u64, food_lifetime: i32, food_interval: i32) -> Self {
+        Self { size, seed, food_lifetime, food_interval, ticks: 0, events: vec![] }
+    }
+
+    pub fn game(&self) -> Game {
+        Game {
+            food_lifetime: self.food_lifetime,
+            food_interval: self.food_interval,
+            ..Game::seeded(self.size, self.seed)
+        }
+    }
+
+    pub fn steer(&mut self, game: &mut Game, direction: Direction) {
+        let previous = game.snake.direction;
+        game.snake.set_direction(direction);
+        if game.snake.direction != previous {
+            self.events.push(Event { tick: self.ticks + 1, change: Change::Direction(direction) });
+        }
+    }
+
+    pub fn run(&mut self, game: &mut Game) -> Step {
+        self.ticks += 1;
+        let step = game.run(self.ticks);
+        if let Some(food) = game.food.last().filter(|food| food.time == self.ticks) {
+            self.events.push(Event { tick: self.ticks, change: Change::Food(food.position) });
+        }
+        step
+    }
+
+    pub fn play(&self) -> Playback<'_> {
+        Playback { replay: self, game: self.game(), tick: 0, next: 0 }
+    }
+}
+
+pub struct Playback<'a> {
+    replay: &'a Replay,
+    pub game: Game,
+    pub tick: i32,
+    next: usize,
+}
+
+impl Iterator for Playback<'_> {
+    type Item = Result<Step, Diverged>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.tick >= self.replay.ticks { return None; }
+        self.tick += 1;
+        let events = &self.replay.events[self.next..];
+        let count = events.iter().take_while(|event| event.tick == self.tick).count();
+        let mut spawned = None;
+        for event in &events[..count] {
+            match event.change {
+                Change::Direction(direction) => self.game.snake.set_direction(direction),
+                Change::Food(position) => spawned = Some(position),
+            }
+        }
+        self.next += count;
+        let step = self.game.run(self.tick);
+        let food = self.game.food.last().filter(|food| food.time == self.tick).map(|food| food.position);
+        if food != spawned { return Some(Err(Diverged { tick: self.tick })); }
+        Some(Ok(step))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Position;
+
+    fn record() -> (Replay, Vec<Step>, Game) {
+        let mut replay = Replay::new([10, 10, 1], 7, 25, 5);
+        let mut game = replay.game();
+        let turns = [(3, [0, 1, 0]), (9, [-1, 0, 0]), (10, [1, 0, 0]), (14, [0, -1, 0]), (30, [1, 0, 0])];
+        let mut steps = vec![];
+        for tick in 1..=60 {
+            for (_, direction) in turns.iter().filter(|(at, _)| *at == tick) {
+                replay.steer(&mut game, Position(*direction));
+            }
+            steps.push(replay.run(&mut game));
+        }
+        (replay, steps, game)
+    }
+
+    #[test]
+    fn playback_reproduces_the_recorded_game() {
+        let (replay, steps, game) = record();
+        let mut playback = replay.play();
+        let replayed: Vec<_> = playback.by_ref().map(Result::unwrap).collect();
+        assert_eq!(replayed, steps);
+        assert_eq!(playback.game.snake.parts, game.snake.parts);
+        assert_eq!(playback.game.food, game.food);
+    }
+
+    #[test]
+    fn ignored_turns_are_not_recorded() {
+        let (replay, _, _) = record();
+        let turns = replay.events.iter().filter(|event| matches!(event.change, Change::Direction(_))).count();
+        assert_eq!(turns, 4);
+    }
+
+    #[test]
+    fn survives_a_json_round_trip() {
+        let (replay, _, _) = record();
+        let json = serde_json::to_string(&replay).unwrap();
+        assert!(json.contains(r#"{"tick":3,"direction":[0,1,0]}"#));
+        assert_eq!(serde_json::from_str::<Replay>(&json).unwrap(), replay);
+    }
+
+    #[test]
+    fn tampered_replays_diverge() {
+        let (mut replay, _, _) = record();
+        replay.seed += 1;
+        assert_eq!(replay.play().find_map(Result::err), Some(Diverged { tick: 5 }));
+    }
+}
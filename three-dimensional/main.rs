@@ -282,14 +282,27 @@ fn main() -> Result<(), impl Error> {
     }];
 
     
+    // a replay file downloaded from the server can be passed as the only argument to watch it instead of playing
+    let replay: Option<snake_rules::Replay> = std::env::args().nth(1).map(|path| {
+        let loaded = std::fs::read_to_string(&path).map_err(|error| error.to_string())
+            .and_then(|content| serde_json::from_str(&content).map_err(|error| error.to_string()));
+        loaded.unwrap_or_else(|error| {
+            eprintln!("cannot load replay {path}: {error}");
+            std::process::exit(1);
+        })
+    });
     let snake = Arc::new(Mutex::new(snake::Game {
-        size: [100, 100, 100],
-        snake: snake::Snake::new(),
+        rules: match &replay {
+            Some(replay) => replay.game(),
+            None => snake_rules::Game {
+                food_interval: 40,
+                ..snake_rules::Game::new([100, 100, 100])
+            },
+        },
         transform: Transform {
             scale: Vec3::new(100.0, 100.0, 100.0),
             ..Default::default()
         },
-        food: vec![],
         progress: 0.0,
     }));
 
@@ -297,7 +310,11 @@ fn main() -> Result<(), impl Error> {
     let trail_counter = Arc::clone(&trail); 
     let trailing_counter = Arc::clone(&trailing);
     let snake_counter = Arc::clone(&snake);
+    let replaying = replay.is_some();
     std::thread::spawn(move || {
+        let mut playback = replay.as_ref().map(snake_rules::Replay::play);
+        // a diverged replay stops where it stopped matching
+        let mut diverged = false;
         for iteration in 0.. {
             std::thread::sleep(std::time::Duration::from_millis(1));
             let cubes_locked = cubes_locked_counter.lock().unwrap(); 
@@ -342,7 +359,20 @@ fn main() -> Result<(), impl Error> {
             }
             drop(trailing);
             let mut snake_locked = snake_counter.lock().unwrap();
-            if iteration % 40 == 0 { snake_locked.run(iteration); }
+            if iteration % 40 == 0 {
+                match &mut playback {
+                    Some(_) if diverged => {}
+                    Some(playback) => match playback.next() {
+                        Some(Ok(_)) => snake_locked.rules = playback.game.clone(),
+                        Some(Err(error)) => {
+                            eprintln!("replay no longer matches the rules at tick {}", error.tick);
+                            diverged = true;
+                        }
+                        None => {}
+                    },
+                    None => { snake_locked.run(iteration); }
+                }
+            }
             snake_locked.progress = (iteration % 40) as f32 / 40.0;
             drop(snake_locked);
             let mut cubes_locked = cubes_locked_counter.lock().unwrap();
@@ -445,8 +475,9 @@ fn main() -> Result<(), impl Error> {
                             KeyCode::ArrowRight | KeyCode::ArrowLeft | KeyCode::ArrowUp | KeyCode::ArrowDown |
                             KeyCode::KeyO | KeyCode::KeyL
                         ) => { 
+                            if replaying { return; }
                             let mut snake_locked = snake.lock().unwrap();
-                            let vectors = snake_locked.snake.vectors(&Transform::default());
+                            let vectors = snake::vectors(&Transform::default());
                             let mut best = vectors[0];
                             let camera = match event.physical_key {
                                 Code(KeyCode::ArrowRight) => camera.right(),
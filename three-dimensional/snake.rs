@@ -1,54 +1,27 @@
+use snake_rules::Position;
+
 #[derive(Clone)]
 pub struct Game {
-    pub size: [i32; 3],
-    pub food: Vec<Food>,
-    pub snake: Snake,
+    pub rules: snake_rules::Game,
     pub transform: crate::Transform,
     pub progress: f32,
 }
 
-impl Game {
-    pub fn run(&mut self, iteration: i32) {
-        self.forward();
-        for i in (0..self.food.len()).rev() {
-            if iteration - self.food[i].time > 25000 {
-                self.food.remove(i);
-            }
-        }
-        self.food.push(Food {
-            position: self.unoccupied(),
-            time: iteration,
-        }); 
-    }
+impl std::ops::Deref for Game {
+    type Target = snake_rules::Game;
 
-    pub fn forward(&mut self) {
-        let new_position = (self.snake.parts[self.snake.parts.len() - 1] + self.snake.direction) % self.size;
-        if let Some(_) = self.snake.parts.iter().skip(1).find(|element| element.0 == new_position.0) { 
-            self.snake.parts.drain(..self.snake.parts.len() - 2); 
-        } else if let Some((i, _)) = self.food.iter().enumerate().find(|element| element.1.position == new_position.0) { 
-            self.food.remove(i);
-            if self.snake.parts[0].0 == new_position.0 { 
-                self.snake.parts.drain(..self.snake.parts.len() - 2); 
-            }
-        } else {
-            self.snake.parts.pop_front();
-        }
-        self.snake.parts.push_back(new_position);
+    fn deref(&self) -> &Self::Target {
+        &self.rules
     }
+}
 
-    pub fn unoccupied(&self) -> [i32; 3] {
-        loop {
-            let position = [
-                (rand::random::<f64>() * self.size[0] as f64) as i32,
-                (rand::random::<f64>() * self.size[1] as f64) as i32,
-                (rand::random::<f64>() * self.size[2] as f64) as i32,
-            ];
-            if let Some(_) = self.food.iter().find(|element| element.position == position) { continue; }
-            if self.snake.parts.contains(&Position(position)) { continue; }
-            return position;
-        }
+impl std::ops::DerefMut for Game {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.rules
     }
+}
 
+impl Game {
     pub fn extend_progress(&self, progress: f32, attached: Position, attaching: Position) -> crate::Transform {
         let delta = attaching + -attached;
         let scale = glam::Vec3::new(
@@ -153,98 +126,10 @@ impl Game {
     }
 }
 
-#[derive(Clone, PartialEq)]
-pub struct Food {
-    pub time: i32,
-    pub position: [i32; 3],
-}
-
-#[derive(Clone)]
-pub struct Snake {
-    pub parts: std::collections::VecDeque<Position>,
-    pub direction: Direction,
-}
-
-#[derive(Clone, Copy, PartialEq, Debug)]
-pub struct Position([i32; 3]);
-type Direction = Position;
-
-impl Position {
-    fn vectorize(&self, transform: &crate::Transform) -> glam::Vec3 {
-        transform.rotation * glam::Vec3::new(self.0[0] as f32, self.0[1] as f32, self.0[2] as f32) 
-    }
+fn vectorize(position: Position, transform: &crate::Transform) -> glam::Vec3 {
+    transform.rotation * glam::Vec3::new(position[0] as f32, position[1] as f32, position[2] as f32)
 }
 
-impl std::ops::Add for Position {
-    type Output = Position;
-
-    fn add(self, other: Self) -> Self::Output {
-        Self([
-             self.0[0] + other.0[0],
-             self.0[1] + other.0[1],
-             self.0[2] + other.0[2],
-        ])
-    }
-}
-
-impl std::ops::Neg for Position {
-    type Output = Position;
-
-    fn neg(self) -> Self::Output {
-        Self([
-             -self.0[0],
-             -self.0[1],
-             -self.0[2],
-        ])
-    }
-}
-
-impl std::ops::Index<usize> for Position {
-    type Output = i32;
-    
-    fn index(&self, position: usize) -> &Self::Output {
-        &self.0[position]
-    }
-}
-
-impl std::ops::Rem<[i32; 3]> for Position {
-    type Output = Position;
-
-    fn rem(self, value: [i32; 3]) -> Self::Output {
-        Self([
-            self.0[0].rem_euclid(value[0]),
-            self.0[1].rem_euclid(value[1]),
-            self.0[2].rem_euclid(value[2]),
-        ])
-    }
-}
-
-impl Snake {
-    pub fn new() -> Self {
-        Self {
-            parts: std::collections::VecDeque::from_iter([
-                Position([0, 0, 0]),
-                Position([1, 0, 0]),
-                Position([2, 0, 0]),
-            ]),
-            direction: Position([1, 0, 0]),
-        }
-    }
-
-    pub fn set_direction(&mut self, new_direction: Direction) {
-        let delta = self.parts[self.parts.len() - 1] + -self.parts[self.parts.len() - 2];
-        if new_direction == -delta { return; }
-        self.direction = new_direction;
-    }
-
-    pub fn vectors(&self, transform: &crate::Transform) -> [(glam::Vec3, Position); 6] {
-        [
-            (Position([1, 0, 0]).vectorize(transform), Position([1, 0, 0])),
-            (Position([-1, 0, 0]).vectorize(transform), Position([-1, 0, 0])),
-            (Position([0, 1, 0]).vectorize(transform), Position([0, 1, 0])),
-            (Position([0, -1, 0]).vectorize(transform), Position([0, -1, 0])),
-            (Position([0, 0, 1]).vectorize(transform), Position([0, 0, 1])),
-            (Position([0, 0, -1]).vectorize(transform), Position([0, 0, -1])),
-        ]
-    }
+pub fn vectors(transform: &crate::Transform) -> [(glam::Vec3, Position); 6] {
+    snake_rules::DIRECTIONS.map(|direction| (vectorize(direction, transform), direction))
 }